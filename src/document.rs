@@ -1,16 +1,252 @@
+use crate::highlighting;
+use crate::ControlCharDisplay;
 use crate::FileType;
 use crate::Position;
 use crate::Row;
 use crate::SearchDirection;
+use crate::VerticalDirection;
+use crate::WordCharSet;
+use std::borrow::Cow;
+use std::cmp;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::env;
 use std::fs;
-use std::io::{Error, Write};
+use std::hash::{Hash, Hasher};
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use unicode_segmentation::UnicodeSegmentation;
 
-#[derive(Default)]
+const KILL_RING_SIZE: usize = 16;
+const SEARCH_HISTORY_SIZE: usize = 32;
+const EDIT_POSITIONS_SIZE: usize = 16;
+const UNDO_STACK_SIZE: usize = 256;
+const TAB_WIDTH: usize = 4;
+const COALESCE_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(PartialEq, Copy, Clone)]
+#[non_exhaustive]
+pub enum IndentStyle {
+    Tabs,
+    Spaces,
+}
+
+/// A style problem reported by `Document::lint`
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+#[non_exhaustive]
+pub enum Lint {
+    /// A tab after the first non-whitespace character of a row, used for
+    /// alignment rather than indentation
+    TabForAlignment,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+#[non_exhaustive]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    /// Returns the bytes written at the end of each row
+    #[must_use]
+    pub fn as_bytes(self) -> &'static [u8] {
+        self.as_str().as_bytes()
+    }
+
+    /// Returns the text written at the end of each row
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::CrLf => "\r\n",
+        }
+    }
+
+    /// Returns the line ending used by the first line of `contents`,
+    /// defaulting to `Lf`
+    fn detect(contents: &str) -> Self {
+        match contents.find('\n') {
+            Some(index) if contents.get(..index).is_some_and(|line| line.ends_with('\r')) => Self::CrLf,
+            _ => Self::Lf,
+        }
+    }
+}
+
+/// The data shown in the status bar, see `Document::status_info`
+#[non_exhaustive]
+pub struct StatusInfo {
+    pub file_name: Option<String>,
+    pub file_type: String,
+    pub is_dirty: bool,
+    pub lines: usize,
+    /// The 1-based line of the cursor
+    pub line: usize,
+    /// The 1-based visual column of the cursor, with tabs expanded
+    pub column: usize,
+    pub line_ending: LineEnding,
+}
+
+/// A match of a search with the row it was found on, see
+/// `Document::search_results`
+#[non_exhaustive]
+pub struct SearchResult {
+    pub position: Position,
+    pub row_text: String,
+    /// The grapheme range of the match in the row, end excluded
+    pub range: (usize, usize),
+}
+
+/// The region of the document filled by the last yank, and the kill it came
+/// from, so that `yank_pop` can replace it
+#[derive(Clone)]
+struct Yank {
+    start: Position,
+    end: Position,
+    index: usize,
+}
+
+/// The edits undone together by `Document::undo`, with the content of the
+/// document before the first of them
+#[derive(Clone)]
+struct UndoGroup {
+    before: DocumentSnapshot,
+    /// Where the first edit of the group happened
+    start: Position,
+    /// Where the last edit of the group happened
+    last: Position,
+    /// When the last edit of the group happened, or `None` if no edit can be
+    /// added to the group anymore
+    last_time: Option<Instant>,
+}
+
+/// A saved state of the content of a document, see `Document::snapshot`
+#[derive(Clone)]
+pub struct DocumentSnapshot {
+    rows: Vec<Rc<Row>>,
+    line_ending: LineEnding,
+    line_widths: BTreeMap<usize, usize>,
+}
+
+/// Cloning a document is cheap: the clone shares its rows with the original
+/// until either of them edits a row, which only copies that row
+#[derive(Clone)]
+#[allow(clippy::struct_excessive_bools, clippy::partial_pub_fields)]
 pub struct Document {
-    rows: Vec<Row>,
+    rows: Vec<Rc<Row>>,
     pub file_name: Option<String>,
     dirty: bool,
     file_type: FileType,
+    saved_hashes: Vec<u64>,
+    /// The rows as they were at the last open or save
+    saved_rows: Vec<Rc<Row>>,
+    saved_line_ending: LineEnding,
+    saved_len: u64,
+    kill_ring: VecDeque<String>,
+    kill_ring_size: usize,
+    search_history: VecDeque<String>,
+    /// The search of `search_history` being recalled, if any
+    search_history_index: Option<usize>,
+    last_yank: Option<Yank>,
+    /// The selected text, from the anchor to the cursor
+    selection: Option<(Position, Position)>,
+    lossy: bool,
+    indent_style: IndentStyle,
+    tab_width: usize,
+    control_chars: ControlCharDisplay,
+    reindent_on_paste: bool,
+    auto_indent: bool,
+    overwrite: bool,
+    auto_pair: bool,
+    minimize_whitespace_churn: bool,
+    /// The column at which prose is hard-wrapped on save, if any
+    hard_wrap_column: Option<usize>,
+    detect_type_from_content: bool,
+    /// Whether the file type was chosen with `set_file_type`, which saving
+    /// then keeps
+    file_type_set: bool,
+    word_chars: WordCharSet,
+    /// The word under the cursor highlighted by `update_word_highlight`
+    highlighted_word: Option<String>,
+    /// The brackets marked by `bracket_match_highlight`, with the
+    /// highlighting they had before
+    bracket_highlight: Vec<(Position, highlighting::Type)>,
+    /// Where the last edits happened, most recent first, see
+    /// `last_edit_position`
+    edit_positions: VecDeque<Position>,
+    undo_stack: VecDeque<UndoGroup>,
+    /// How many `as_one_edit` calls are running, during which the oldest
+    /// undo groups are kept so that the stack can be truncated back
+    grouping: usize,
+    redo_stack: Vec<(DocumentSnapshot, Position)>,
+    coalesce_window: Duration,
+    line_ending: LineEnding,
+    /// How many rows there are of each display width
+    line_widths: BTreeMap<usize, usize>,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self {
+            rows: Vec::new(),
+            file_name: None,
+            dirty: false,
+            file_type: FileType::default(),
+            saved_hashes: Vec::new(),
+            saved_rows: Vec::new(),
+            saved_line_ending: LineEnding::Lf,
+            saved_len: 0,
+            kill_ring: VecDeque::new(),
+            kill_ring_size: KILL_RING_SIZE,
+            search_history: VecDeque::new(),
+            search_history_index: None,
+            last_yank: None,
+            selection: None,
+            lossy: false,
+            indent_style: IndentStyle::Tabs,
+            tab_width: TAB_WIDTH,
+            control_chars: ControlCharDisplay::default(),
+            reindent_on_paste: false,
+            auto_indent: false,
+            overwrite: false,
+            auto_pair: false,
+            minimize_whitespace_churn: false,
+            hard_wrap_column: None,
+            detect_type_from_content: false,
+            file_type_set: false,
+            word_chars: WordCharSet::default(),
+            highlighted_word: None,
+            bracket_highlight: Vec::new(),
+            edit_positions: VecDeque::new(),
+            undo_stack: VecDeque::new(),
+            grouping: 0,
+            redo_stack: Vec::new(),
+            coalesce_window: COALESCE_WINDOW,
+            line_ending: LineEnding::Lf,
+            line_widths: BTreeMap::new(),
+        }
+    }
+}
+
+/// Documents are equal when they have the same file name and the same text,
+/// whatever their highlighting, history or dirty state
+impl PartialEq for Document {
+    fn eq(&self, other: &Self) -> bool {
+        self.file_name == other.file_name && self.rows == other.rows
+    }
+}
+
+impl Eq for Document {}
+
+impl Hash for Document {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.file_name.hash(state);
+        self.rows.hash(state);
+    }
 }
 
 impl Document {
@@ -19,18 +255,180 @@ impl Document {
     /// # Errors
     /// It will return `Err` if it fails to open the file
     pub fn open(filename: &str) -> Result<Self, std::io::Error> {
-        let contents = fs::read_to_string(filename)?;
-        let file_type = FileType::from(filename);
+        let mut document = Self::from_reader(fs::File::open(filename)?)?;
+        document.file_type = FileType::detect(filename, document.rows.first().map(|row| row.as_str()));
+        document.file_name = Some(filename.to_owned());
+        Ok(document)
+    }
+
+    /// Returns the path of a file related to this one that exists on disk,
+    /// e.g. to switch between them: `foo.h` for `foo.c` and back, `foo.rs`
+    /// for `foo/mod.rs` and back, or `main.rs` for `lib.rs` and back
+    #[must_use]
+    pub fn alternate_file(&self) -> Option<String> {
+        let path = Path::new(self.file_name.as_deref()?);
+        let stem = path.file_stem()?.to_str()?;
+        let candidate = match (path.extension()?.to_str()?, stem) {
+            ("c", _) => path.with_extension("h"),
+            ("h", _) => path.with_extension("c"),
+            ("rs", "mod") => path.parent()?.with_extension("rs"),
+            ("rs", "lib") => path.with_file_name("main.rs"),
+            ("rs", "main") => path.with_file_name("lib.rs"),
+            ("rs", _) => path.with_extension("").join("mod.rs"),
+            _ => return None,
+        };
+        if !candidate.is_file() {
+            return None;
+        }
+        candidate.to_str().map(str::to_owned)
+    }
+
+    /// Opens a file like `open`, after expanding a leading `~` to the home
+    /// directory and `$VAR` or `${VAR}` to the value of the environment
+    /// variable. The expanded path becomes the `file_name`, so that saving
+    /// writes to the same file. Variables that are not set are left as is.
+    ///
+    /// # Errors
+    /// It will return `Err` if it fails to open the file
+    pub fn open_expanded(path: &str) -> Result<Self, std::io::Error> {
+        Self::open(&expand_path(path, |name| env::var(name).ok()))
+    }
+
+    /// Opens a file given as `path[:line[:column]]`, e.g. `main.rs:42:8`, and
+    /// returns it with the 0-based position of that 1-based line and column,
+    /// clamped to the document. A drive letter such as `C:\` is part of the
+    /// path since only numbers after the last colons are parsed.
+    ///
+    /// # Errors
+    /// It will return `Err` if it fails to open the file
+    pub fn open_at(spec: &str) -> Result<(Self, Position), std::io::Error> {
+        let (filename, line, column) = split_position_spec(spec);
+        let document = Self::open(filename)?;
+        let position = document.spec_position(line, column);
+        Ok((document, position))
+    }
+
+    /// Opens a file given as `path[:line[:column]]` like `open_at`, even if
+    /// it is not valid UTF-8, see `open_lossy`.
+    ///
+    /// # Errors
+    /// It will return `Err` if it fails to read the file
+    pub fn open_lossy_at(spec: &str) -> Result<(Self, Position), std::io::Error> {
+        let (filename, line, column) = split_position_spec(spec);
+        let document = Self::open_lossy(filename)?;
+        let position = document.spec_position(line, column);
+        Ok((document, position))
+    }
+
+    /// Returns the 0-based position of the 1-based `line` and `column` of a
+    /// `path[:line[:column]]` spec, clamped to the document
+    fn spec_position(&self, line: Option<usize>, column: Option<usize>) -> Position {
+        self.restore_cursor(&Position {
+            x: column.unwrap_or(1).saturating_sub(1),
+            y: line.unwrap_or(1).saturating_sub(1),
+        })
+    }
+
+    /// Reads a document from any reader, e.g. the standard input. The
+    /// document has no file name and no file type.
+    ///
+    /// # Errors
+    /// It will return `Err` if it fails to read from `reader` or the content
+    /// is not valid UTF-8
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self, std::io::Error> {
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents)?;
+        Ok(Self::from_contents(&contents))
+    }
+
+    /// Opens a file in the editor even if it is not valid UTF-8, replacing
+    /// the invalid sequences with `U+FFFD`. Saving such a document writes
+    /// the replacement characters back, see `is_lossy`.
+    ///
+    /// # Errors
+    /// It will return `Err` if it fails to read the file
+    pub fn open_lossy(filename: &str) -> Result<Self, std::io::Error> {
+        let bytes = fs::read(filename)?;
+        let contents = String::from_utf8_lossy(&bytes);
+        let mut document = Self::from_contents(&contents);
+        document.lossy = matches!(contents, Cow::Owned(_));
+        document.file_type = FileType::detect(filename, document.rows.first().map(|row| row.as_str()));
+        document.file_name = Some(filename.to_owned());
+        Ok(document)
+    }
+
+    /// Builds a document from text with the ANSI escape sequences removed,
+    /// e.g. colored output pasted from a terminal
+    #[must_use]
+    pub fn from_str_stripping_ansi(contents: &str) -> Self {
+        Self::from_contents(&strip_ansi(contents))
+    }
+
+    fn from_contents(contents: &str) -> Self {
         let mut rows = Vec::new();
         for value in contents.lines() {
-            rows.push(Row::from(value));
+            rows.push(Rc::new(Row::from(value)));
         }
-        Ok(Self {
+        let mut document = Self {
             rows,
-            file_name: Some(filename.to_owned()),
-            dirty: false,
-            file_type,
-        })
+            line_ending: LineEnding::detect(contents),
+            ..Self::default()
+        };
+        document.count_line_widths();
+        document.take_snapshot();
+        document
+    }
+
+    /// Returns a new document holding the rows from `start_y` to `end_y`,
+    /// both included, e.g. to move them to a file of their own. It has the
+    /// same file type and line ending, no file name, and is dirty since it
+    /// was never saved.
+    #[must_use]
+    pub fn extract_rows(&self, start_y: usize, end_y: usize) -> Self {
+        let rows = self
+            .rows
+            .get(start_y..=end_y.min(self.rows.len().saturating_sub(1)))
+            .unwrap_or_default()
+            .to_vec();
+        let mut document = Self {
+            rows,
+            dirty: true,
+            file_type: self.file_type.clone(),
+            line_ending: self.line_ending,
+            indent_style: self.indent_style,
+            tab_width: self.tab_width,
+            word_chars: self.word_chars,
+            ..Self::default()
+        };
+        document.count_line_widths();
+        document
+    }
+
+    /// Returns a key identifying the file of the document, its canonical
+    /// absolute path, e.g. to remember the cursor position per file
+    #[must_use]
+    pub fn cursor_state_key(&self) -> Option<String> {
+        let file_name = self.file_name.as_ref()?;
+        let path = fs::canonicalize(file_name)
+            .or_else(|_| std::env::current_dir().map(|dir| dir.join(file_name)))
+            .ok()?;
+        Some(path.to_string_lossy().into_owned())
+    }
+
+    /// Returns the closest position to a remembered `position` that is still
+    /// inside the document
+    #[must_use]
+    pub fn restore_cursor(&self, position: &Position) -> Position {
+        let y = position.y.min(self.rows.len().saturating_sub(1));
+        let x = position.x.min(self.rows.get(y).map_or(0, |row| row.len()));
+        Position { x, y }
+    }
+
+    /// Returns a boolean indicating if invalid UTF-8 was replaced when the
+    /// document was opened, meaning that saving it changes those bytes
+    #[must_use]
+    pub fn is_lossy(&self) -> bool {
+        self.lossy
     }
 
     /// Gets the name of the file that we are opening on the editor
@@ -42,7 +440,44 @@ impl Document {
     /// Gets the row based on an `index`
     #[must_use]
     pub fn row(&self, index: usize) -> Option<&Row> {
-        self.rows.get(index)
+        self.rows.get(index).map(Rc::as_ref)
+    }
+
+    /// Returns the number of lines the cursor can be on: the rows, and a
+    /// virtual empty line after them unless the last row is already empty.
+    /// Typing on the virtual line makes it a real row, see `len`.
+    #[must_use]
+    pub fn line_count_with_virtual(&self) -> usize {
+        if self.rows.last().is_some_and(|row| row.is_empty()) {
+            self.rows.len()
+        } else {
+            self.rows.len().saturating_add(1)
+        }
+    }
+
+    /// Gets the row based on an `index` to change it, e.g. from an extension.
+    /// The document is marked dirty and rehighlighted from that row on, as
+    /// if the row had been edited. Callers that change the row must call
+    /// `rehighlight_from` once done, so that the display widths are
+    /// recounted.
+    pub fn row_mut(&mut self, index: usize) -> Option<&mut Row> {
+        if index >= self.rows.len() {
+            return None;
+        }
+        self.record_edit(&Position { x: 0, y: index });
+        self.track_edit(&Position { x: 0, y: index }, Position::clone);
+        self.dirty = true;
+        self.last_yank = None;
+        self.selection = None;
+        self.unhighlight_rows(index);
+        self.rows.get_mut(index).map(Rc::make_mut)
+    }
+
+    /// Rehighlights the document from the row at `index` on and recounts the
+    /// display widths of the rows, after changing rows through `row_mut`
+    pub fn rehighlight_from(&mut self, index: usize) {
+        self.unhighlight_rows(index);
+        self.count_line_widths();
     }
 
     /// Check if `rows` is empty.
@@ -53,6 +488,10 @@ impl Document {
 
     #[must_use]
     /// Get the length of `rows`
+    ///
+    /// An empty file has no rows at all, the line after the last row is only
+    /// where the cursor can be. Typing a character there creates a row, and
+    /// inserting a newline there creates a blank one.
     pub fn len(&self) -> usize {
         self.rows.len()
     }
@@ -62,159 +501,3799 @@ impl Document {
             return;
         }
         if at.y == self.rows.len() {
-            self.rows.push(Row::default());
+            self.rows.push(Rc::new(Row::default()));
+            self.add_line_width(0);
             return;
         }
 
-        let current_row = self
-            .rows
-            .get_mut(at.y)
-            .expect("Something unexpected happened while trying to index rows.");
-
-        let new_row = current_row.split(at.x);
-
-        self.rows.insert(at.y.saturating_add(1), new_row);
+        let old_width = self.line_width(at.y);
+        if let Some(current_row) = self.rows.get_mut(at.y) {
+            let new_row = Rc::make_mut(current_row).split(at.x);
+            self.rows.insert(at.y.saturating_add(1), Rc::new(new_row));
+            self.update_line_width(at.y, old_width);
+            self.update_line_width(at.y.saturating_add(1), None);
+        }
     }
 
     /// Inserts a character in the document that is being read, at the position
     /// where the cursor is.
     ///
-    /// # Panics
-    ///
-    /// It will panic if we try to insert in a position that is greater
-    /// than the length of the document.
+    /// It does nothing if `at.y` is past the end of the document, and appends
+    /// the character to the row if `at.x` is past the end of the row.
     pub fn insert(&mut self, at: &Position, c: char) {
         if at.y > self.rows.len() {
             return;
         }
+        let at = &Position {
+            x: self.rows.get(at.y).map_or(0, |row| at.x.min(row.len())),
+            y: at.y,
+        };
+        self.record_edit(at);
+        if c == '\n' {
+            self.track_edit(at, |position| match position.y.cmp(&at.y) {
+                cmp::Ordering::Equal if position.x >= at.x => Position {
+                    x: position.x.saturating_sub(at.x),
+                    y: at.y.saturating_add(1),
+                },
+                cmp::Ordering::Greater => Position {
+                    x: position.x,
+                    y: position.y.saturating_add(1),
+                },
+                cmp::Ordering::Less | cmp::Ordering::Equal => position.clone(),
+            });
+        } else {
+            self.track_edit(at, |position| Position {
+                x: if position.y == at.y && position.x >= at.x {
+                    position.x.saturating_add(1)
+                } else {
+                    position.x
+                },
+                y: position.y,
+            });
+        }
         self.dirty = true;
+        self.last_yank = None;
+        self.selection = None;
         if c == '\n' {
             self.insert_newline(at);
         } else if at.y == self.rows.len() {
             let mut row = Row::default();
             row.insert(0, c);
-            self.rows.push(row);
+            self.rows.push(Rc::new(row));
+            self.update_line_width(at.y, None);
         } else {
-            #[allow(clippy::indexing_slicing)]
-            let row = &mut self.rows[at.y];
-            row.insert(at.x, c);
+            let old_width = self.line_width(at.y);
+            if let Some(row) = self.rows.get_mut(at.y) {
+                let row = Rc::make_mut(row);
+                let opts = self.file_type.highlighting_options();
+                if self.highlighted_word.is_none() && row.insert_in_word(at.x, c, opts) {
+                    self.update_line_width(at.y, old_width);
+                    return;
+                }
+                row.insert(at.x, c);
+            }
+            self.update_line_width(at.y, old_width);
         }
         self.unhighlight_rows(at.y);
     }
 
+    /// Marks the rows from the one before `start` to be highlighted again,
+    /// which drops the word highlighted by `update_word_highlight` from them
     fn unhighlight_rows(&mut self, start: usize) {
+        self.highlighted_word = None;
         let start = start.saturating_sub(1);
         for row in self.rows.iter_mut().skip(start) {
-            row.is_highlighted = false;
+            if row.is_highlighted {
+                Rc::make_mut(row).is_highlighted = false;
+            }
         }
     }
 
     /// Deletes a single or multiple characters in the document
-    #[allow(clippy::integer_arithmetic)]
+    ///
+    /// Deleting at the end of a row joins it with the next one. It does
+    /// nothing if there is no character at `at`.
     pub fn delete(&mut self, at: &Position) {
-        let len = self.rows.len();
-        if at.y >= len {
+        let has_next_row = at.y.saturating_add(1) < self.rows.len();
+        let Some(row_len) = self.rows.get(at.y).map(|row| row.len()) else {
+            return;
+        };
+        if at.x > row_len || (at.x == row_len && !has_next_row) {
             return;
         }
-        self.dirty = true;
-        if at.x == self.rows.get_mut(at.y).expect("Something unexpected happened while trying to get a mutable reference to the row index").len() && at.y + 1 < len {
-            let next_row = self.rows.remove(at.y + 1);
-            let row = self.rows.get_mut(at.y).expect("Something unexpected happened while trying to get a mutable reference to the row index");
-            row.append(&next_row);
+        self.record_edit(at);
+        let next_y = at.y.saturating_add(1);
+        if at.x == row_len {
+            self.track_edit(at, |position| match position.y.cmp(&next_y) {
+                cmp::Ordering::Equal => Position {
+                    x: position.x.saturating_add(row_len),
+                    y: at.y,
+                },
+                cmp::Ordering::Greater => Position {
+                    x: position.x,
+                    y: position.y.saturating_sub(1),
+                },
+                cmp::Ordering::Less => position.clone(),
+            });
         } else {
-            let row = self.rows.get_mut(at.y).expect("Something unexpected happened while trying to get a mutable reference to the row index");
-            row.delete(at.x);
+            self.track_edit(at, |position| Position {
+                x: if position.y == at.y && position.x > at.x {
+                    position.x.saturating_sub(1)
+                } else {
+                    position.x
+                },
+                y: position.y,
+            });
         }
+        self.dirty = true;
+        self.last_yank = None;
+        self.selection = None;
+        let old_width = self.line_width(at.y);
+        if at.x == row_len {
+            if let Some(next_width) = self.line_width(at.y.saturating_add(1)) {
+                self.remove_line_width(next_width);
+            }
+            let next_row = self.rows.remove(at.y.saturating_add(1));
+            if let Some(row) = self.rows.get_mut(at.y) {
+                Rc::make_mut(row).append(&next_row);
+            }
+        } else if let Some(row) = self.rows.get_mut(at.y) {
+            Rc::make_mut(row).delete(at.x);
+        }
+        self.update_line_width(at.y, old_width);
         self.unhighlight_rows(at.y);
     }
 
-    /// Saves the changes in the document
+    /// Splits the row at the given position and returns the start of the new
+    /// row. With auto-indent, the new row starts with the indentation of the
+    /// row that was split and the position is right after it.
     ///
-    /// # Errors
-    ///
-    /// It will return `Err` if `file_name` does not exist or the user
-    /// does not have the permission to write to it
-    pub fn save(&mut self) -> Result<(), Error> {
-        if let Some(ref file_name) = self.file_name {
-            let mut file = fs::File::create(file_name)?;
-            self.file_type = FileType::from(file_name);
-            for row in &mut self.rows {
-                file.write_all(row.as_bytes())?;
-                file.write_all(b"\n")?;
-            }
-            self.dirty = false;
+    /// Splitting a line comment after its marker continues the comment: the
+    /// new row starts with the same indentation and marker. A comment that
+    /// is only a marker is not continued, so that pressing Enter twice ends
+    /// the comment.
+    pub fn split_line(&mut self, at: &Position) -> Position {
+        let indent: String = if let Some(prefix) = self.comment_continuation(at) {
+            prefix
+        } else if self.auto_indent {
+            self.rows.get(at.y).map_or_else(String::new, |row| {
+                row.slice(0, at.x).chars().take_while(|c| *c == ' ' || *c == '\t').collect()
+            })
+        } else {
+            String::new()
+        };
+        self.insert(at, '\n');
+        self.insert_str(&Position { x: 0, y: at.y.saturating_add(1) }, &indent)
+    }
+
+    /// Returns the indentation, line comment marker and the spaces after it
+    /// that start the row at `at.y`, if the row up to `at.x` is a line
+    /// comment with some text after the marker
+    fn comment_continuation(&self, at: &Position) -> Option<String> {
+        let marker = self.file_type.line_comment()?;
+        let head = self.rows.get(at.y)?.slice(0, at.x);
+        let rest = head.trim_start_matches([' ', '\t']).strip_prefix(marker)?;
+        let repeated = marker.chars().last()?;
+        let text = rest.trim_start_matches(repeated).trim_start_matches(' ');
+        if text.is_empty() {
+            return None;
         }
-        Ok(())
+        head.get(..head.len().saturating_sub(text.len())).map(str::to_owned)
     }
 
-    /// Loop over the rows and highligh the words that correspond
-    /// the word that was passed as a parameter.
-    pub fn highlight(&mut self, word: &Option<String>, until: Option<usize>) {
-        let mut start_with_comment = false;
-        let until = if let Some(until) = until {
-            if until.saturating_add(1) < self.rows.len() {
-                until.saturating_add(1)
-            } else {
-                self.rows.len()
+    /// Deletes the character before the given position, joining the row with
+    /// the previous one at the start of a row, and returns the position where
+    /// the cursor should move. Within the indentation of a row, it removes
+    /// the spaces back to the previous tab stop like `remove_indent`.
+    pub fn backspace(&mut self, at: &Position) -> Position {
+        let x = self.rows.get(at.y).map_or(0, |row| at.x.min(row.len()));
+        if x > 0 {
+            let indent = self.remove_indent(&Position { x, y: at.y });
+            if indent.x < x {
+                return indent;
+            }
+        }
+        let position = if x > 0 {
+            Position {
+                x: x.saturating_sub(1),
+                y: at.y,
+            }
+        } else if at.y > 0 {
+            let y = at.y.saturating_sub(1);
+            Position {
+                x: self.rows.get(y).map_or(0, |row| row.len()),
+                y,
             }
         } else {
-            self.rows.len()
+            return at.clone();
         };
+        self.delete(&position);
+        position
+    }
 
-        let rows_indexed = self.rows.get_mut(..until).expect("Failed while trying to index rows");
-        for row in rows_indexed {
-            start_with_comment = row.highlight(self.file_type.highlighting_options(), word, start_with_comment);
-        
-        }
+    /// Selects the text between `anchor` and `head`, in either order. The
+    /// selection is cleared by any edit.
+    pub fn set_selection(&mut self, anchor: &Position, head: &Position) {
+        self.selection = Some((anchor.clone(), head.clone()));
     }
 
-    /// Returns a boolean indicating if the document has been changed or not
+    /// Selects the whole document, from its start to the end of its last row
+    pub fn select_all(&mut self) {
+        let end = Position {
+            x: self.rows.last().map_or(0, |row| row.len()),
+            y: self.rows.len().saturating_sub(1),
+        };
+        self.selection = Some((Position::default(), end));
+    }
+
+    /// Selects the row `y` with the line break after it, up to the start of
+    /// the next row, or to its end for the last row
+    pub fn select_line(&mut self, y: usize) {
+        let Some(row) = self.rows.get(y) else {
+            return;
+        };
+        let end = if y.saturating_add(1) < self.rows.len() {
+            Position { x: 0, y: y.saturating_add(1) }
+        } else {
+            Position { x: row.len(), y }
+        };
+        self.selection = Some((Position { x: 0, y }, end));
+    }
+
+    /// Returns the selected text, if any, with rows joined by `\n`
     #[must_use]
-    pub fn is_dirty(&self) -> bool {
-        self.dirty
+    pub fn selected_text(&self) -> Option<String> {
+        let (start, end) = self.selection()?;
+        Some(self.text(&start, &end))
     }
 
-    /// Returns an option with the elements that corresponds to a certain
-    /// search query passed
+    /// Clears the selection, if any
+    pub fn clear_selection(&mut self) {
+        self.selection = None;
+    }
+
+    /// Returns the start and end of the selection, if any, in document order
+    #[must_use]
+    pub fn selection(&self) -> Option<(Position, Position)> {
+        let (anchor, head) = self.selection.clone()?;
+        if head.is_before(&anchor) {
+            Some((head, anchor))
+        } else {
+            Some((anchor, head))
+        }
+    }
+
+    /// Inserts a character like `insert`, but first deletes the selection if
+    /// there is one and inserts the character in its place. Without a
+    /// selection in overwrite mode, the character replaces the one at `at`
+    /// unless it is a newline. With auto-pairing, brackets and quotes are
+    /// typed as pairs, see `set_auto_pair`. Returns the position right after
+    /// the character.
+    pub fn type_char(&mut self, at: &Position, c: char) -> Position {
+        if let Some((start, _)) = self.selection() {
+            return self.as_one_edit(&start, |document| {
+                document.delete_selection();
+                document.insert_str(&start, &c.to_string())
+            });
+        }
+        if self.auto_pair && !self.overwrite {
+            if let Some(position) = self.type_pair(at, c) {
+                return position;
+            }
+        }
+        if self.overwrite && c != '\n' && self.rows.get(at.y).is_some_and(|row| at.x < row.len()) {
+            return self.as_one_edit(at, |document| {
+                document.delete(at);
+                document.insert_str(at, &c.to_string())
+            });
+        }
+        self.insert_str(at, &c.to_string())
+    }
+
+    /// Sets whether `type_char` replaces the character under the cursor
+    /// instead of inserting before it
+    pub fn set_overwrite(&mut self, overwrite: bool) {
+        self.overwrite = overwrite;
+    }
+
+    /// Returns a boolean indicating if the document is in overwrite mode
     #[must_use]
-    pub fn find(&self, query: &str, at: &Position, direction: SearchDirection) -> Option<Position> {
-        if at.y >= self.rows.len() {
+    pub fn is_overwrite(&self) -> bool {
+        self.overwrite
+    }
+
+    /// Sets the characters words are made of, for `delete_word`,
+    /// `expand_to_word_boundaries` and whole-word search
+    pub fn set_word_chars(&mut self, word_chars: WordCharSet) {
+        self.word_chars = word_chars;
+    }
+
+    /// Sets whether `type_char` closes the brackets and quotes it opens
+    pub fn set_auto_pair(&mut self, auto_pair: bool) {
+        self.auto_pair = auto_pair;
+    }
+
+    /// Types `c` as part of a pair of brackets or quotes, if it is one.
+    /// A closing character typed right before the same one steps over it,
+    /// and an opening one is inserted together with its closing character,
+    /// with the position between them returned. Quotes are not paired next
+    /// to a word character, so that apostrophes and lifetimes stay single.
+    fn type_pair(&mut self, at: &Position, c: char) -> Option<Position> {
+        let row = self.rows.get(at.y);
+        let x = row.map_or(0, |row| at.x.min(row.len()));
+        let grapheme_at = |index: usize| row?.slice(index, index.saturating_add(1)).chars().next();
+        let next = grapheme_at(x);
+        let previous = x.checked_sub(1).and_then(grapheme_at);
+        let is_quote = matches!(c, '"' | '\'' | '`');
+        let after = Position {
+            x: x.saturating_add(1),
+            y: at.y,
+        };
+        if (is_quote || matches!(c, ')' | ']' | '}')) && next == Some(c) {
+            return Some(after);
+        }
+        let close = match c {
+            '(' => ')',
+            '[' => ']',
+            '{' => '}',
+            '"' | '\'' | '`' => c,
+            _ => return None,
+        };
+        let is_word = |neighbour: Option<char>| neighbour.is_some_and(|n| n.is_alphanumeric() || n == '_');
+        if is_quote && (is_word(previous) || is_word(next)) {
             return None;
         }
+        self.insert_str(&Position { x, y: at.y }, &format!("{c}{close}"));
+        Some(after)
+    }
 
-        let mut position = Position { x: at.x, y: at.y };
+    /// Deletes the selected text, if any, and returns where it started
+    pub fn delete_selection(&mut self) -> Option<Position> {
+        let (start, end) = self.selection()?;
+        self.delete_range(&start, &end);
+        Some(start)
+    }
 
-        let start = if direction == SearchDirection::Forward {
-            at.y
+    /// Removes the text between `start` and `end` and returns it. Unlike
+    /// `cut`, it does not go to the kill ring.
+    pub fn delete_range(&mut self, start: &Position, end: &Position) -> String {
+        self.remove(start, end)
+    }
+
+    /// Inserts a string in the document at the given position and returns
+    /// the position right after the inserted text. Each `\r\n`, `\r` or `\n`
+    /// in `string` starts a new row, e.g. in text pasted from elsewhere.
+    pub fn insert_str(&mut self, at: &Position, string: &str) -> Position {
+        let mut position = at.clone();
+        let mut chars = string.chars().peekable();
+        while let Some(mut c) = chars.next() {
+            if c == '\r' {
+                chars.next_if_eq(&'\n');
+                c = '\n';
+            }
+            self.insert(&position, c);
+            if c == '\n' {
+                position.y = position.y.saturating_add(1);
+                position.x = 0;
+            } else {
+                position.x = position.x.saturating_add(1);
+            }
+        }
+        position
+    }
+
+    /// Moves the text between `start` and `end` to `dest`, as a single edit
+    /// for `undo`, and returns the position right after the moved text. A
+    /// `dest` after the range refers to the document before the move. It
+    /// does nothing if `dest` is within the range.
+    pub fn move_range(&mut self, start: &Position, end: &Position, dest: &Position) -> Position {
+        let (start, end) = if end.is_before(start) { (end, start) } else { (start, end) };
+        if !dest.is_before(start) && !end.is_before(dest) {
+            return dest.clone();
+        }
+        let dest = if dest.is_before(start) {
+            dest.clone()
+        } else if dest.y == end.y {
+            Position {
+                x: start.x.saturating_add(dest.x.saturating_sub(end.x)),
+                y: start.y,
+            }
         } else {
-            0
+            Position {
+                x: dest.x,
+                y: dest.y.saturating_sub(end.y.saturating_sub(start.y)),
+            }
         };
+        self.as_one_edit(&dest, |document| {
+            let text = document.remove(start, end);
+            document.insert_str(&dest, &text)
+        })
+    }
 
-        let end = if direction == SearchDirection::Forward {
-            self.rows.len()
-        } else {
-            at.y.saturating_add(1)
+    /// Swaps the rows `a` and `b`, as a single edit for `undo`, and
+    /// highlights again from the first of them on, since the rows in between
+    /// may now start in another state. It does nothing if either row does
+    /// not exist.
+    pub fn swap_rows(&mut self, a: usize, b: usize) {
+        if a == b || a >= self.rows.len() || b >= self.rows.len() {
+            return;
+        }
+        let at = Position { x: 0, y: a.min(b) };
+        self.as_one_edit(&at, |document| {
+            document.record_edit(&at);
+            document.track_edit(&at, Position::clone);
+            document.dirty = true;
+            document.last_yank = None;
+            document.selection = None;
+            document.rows.swap(a, b);
+            document.unhighlight_rows(at.y);
+            at.clone()
+        });
+    }
+
+    /// Replaces the text between `start` and `end` with `text` and returns
+    /// the position right after the new text. It is undone in one step.
+    pub fn replace_range(&mut self, start: &Position, end: &Position, text: &str) -> Position {
+        self.as_one_edit(start, |document| {
+            document.remove(start, end);
+            document.insert_str(start, text)
+        })
+    }
+
+    /// Replaces the text between `start` and `end` with the result of `f`
+    /// over it, e.g. to sort words or apply rot13, and returns the position
+    /// right after the new text.
+    pub fn transform_range<F: Fn(&str) -> String>(&mut self, start: &Position, end: &Position, f: F) -> Position {
+        let text = f(&self.text(start, end));
+        self.replace_range(start, end, &text)
+    }
+
+    /// Wraps the text between `start` and `end` in the block comment
+    /// delimiters of the file type, or unwraps it if it is already wrapped,
+    /// and returns the new end of the text. It does nothing if the file type
+    /// has no block comments.
+    pub fn toggle_block_comment(&mut self, start: &Position, end: &Position) -> Position {
+        let Some((open, close)) = self.file_type.block_comment() else {
+            return end.clone();
         };
+        let (open, close) = (open.to_owned(), close.to_owned());
+        self.transform_range(start, end, |text| {
+            text.strip_prefix(open.as_str())
+                .and_then(|inner| inner.strip_suffix(close.as_str()))
+                .map_or_else(|| format!("{open}{text}{close}"), str::to_owned)
+        })
+    }
 
-        for _ in start..end {
-            if let Some(row) = self.rows.get(position.y) {
-                if let Some(x) = row.find(query, position.x, direction) {
-                    position.x = x;
-                    return Some(position);
-                }
-                if direction == SearchDirection::Forward {
-                    position.y = position.y.saturating_add(1);
-                    position.x = 0;
-                } else {
-                    position.y = position.y.saturating_sub(1);
-                    if let Some(r) = self.rows.get(position.y) {
-                        position.x = r.len();
-                    }
-                }
-            } else {
-                return None;
+    /// Saves the current content of the document, to be brought back with
+    /// `restore`. Rows are shared with the document until either is edited,
+    /// so this does not copy the text.
+    #[must_use]
+    pub fn snapshot(&self) -> DocumentSnapshot {
+        DocumentSnapshot {
+            rows: self.rows.clone(),
+            line_ending: self.line_ending,
+            line_widths: self.line_widths.clone(),
+        }
+    }
+
+    /// Reverts the content of the document to a previous `snapshot`. It is
+    /// dirty unless the snapshot matches what was last opened or saved.
+    pub fn restore(&mut self, snapshot: DocumentSnapshot) {
+        self.rows = snapshot.rows;
+        self.line_ending = snapshot.line_ending;
+        self.line_widths = snapshot.line_widths;
+        self.last_yank = None;
+        self.selection = None;
+        self.highlighted_word = None;
+        self.dirty = self.differs_from_saved();
+    }
+
+    /// Returns whether the rows or the line ending differ from those at the
+    /// last open or save
+    fn differs_from_saved(&self) -> bool {
+        self.line_ending != self.saved_line_ending
+            || self.rows.len() != self.saved_rows.len()
+            || self
+                .rows
+                .iter()
+                .zip(&self.saved_rows)
+                .any(|(row, saved)| !Rc::ptr_eq(row, saved) && row.as_bytes() != saved.as_bytes())
+    }
+
+    /// Pushes `group` on the undo stack, forgetting the oldest groups once
+    /// the stack holds more than `UNDO_STACK_SIZE` of them, unless inside
+    /// `as_one_edit`
+    fn push_undo_group(&mut self, group: UndoGroup) {
+        self.undo_stack.push_back(group);
+        if self.grouping == 0 {
+            while self.undo_stack.len() > UNDO_STACK_SIZE {
+                self.undo_stack.pop_front();
             }
         }
-        None
+    }
+
+    /// Closes the current undo group, so that the next edit starts a new one
+    /// however soon and close it comes
+    pub fn end_undo_group(&mut self) {
+        if let Some(group) = self.undo_stack.back_mut() {
+            group.last_time = None;
+        }
+    }
+
+    /// Sets how close in time edits must be to be undone together
+    pub fn set_coalesce_window(&mut self, coalesce_window: Duration) {
+        self.coalesce_window = coalesce_window;
+    }
+
+    /// Remembers the content of the document before an edit at `at`, unless
+    /// the edit continues the current undo group: it must come within the
+    /// coalesce window of the previous edit, on the same or a neighbouring
+    /// row and at most one grapheme away on the same row.
+    fn record_edit(&mut self, at: &Position) {
+        self.redo_stack.clear();
+        let now = Instant::now();
+        if let Some(group) = self.undo_stack.back_mut() {
+            let contiguous = if at.y == group.last.y {
+                at.x.abs_diff(group.last.x) <= 1
+            } else {
+                at.y.abs_diff(group.last.y) == 1
+            };
+            let recent = group
+                .last_time
+                .is_some_and(|last_time| now.duration_since(last_time) <= self.coalesce_window);
+            if contiguous && recent {
+                group.last = at.clone();
+                group.last_time = Some(now);
+                return;
+            }
+        }
+        let before = self.snapshot();
+        self.push_undo_group(UndoGroup {
+            before,
+            start: at.clone(),
+            last: at.clone(),
+            last_time: Some(now),
+        });
+    }
+
+    /// Moves the positions of the last edits with `shift`, so that they
+    /// follow the text through an edit at `at`, and remembers `at` as the
+    /// most recent one. An edit on the same row as the previous one replaces
+    /// it, so that typing a word leaves a single position.
+    fn track_edit<F: Fn(&Position) -> Position>(&mut self, at: &Position, shift: F) {
+        for position in &mut self.edit_positions {
+            *position = shift(position);
+        }
+        if self.edit_positions.front().is_some_and(|last| last.y == at.y) {
+            self.edit_positions.pop_front();
+        }
+        self.edit_positions.push_front(at.clone());
+        self.edit_positions.truncate(EDIT_POSITIONS_SIZE);
+    }
+
+    /// Returns where the `n`th most recent edit happened, counting from 0,
+    /// moved along by the edits made since, e.g. to go back to the last edit
+    #[must_use]
+    pub fn last_edit_position(&self, n: usize) -> Option<Position> {
+        self.edit_positions.get(n).cloned()
+    }
+
+    /// Runs `edit` as a single undo group starting at `at`, whatever the
+    /// positions of the edits it makes
+    fn as_one_edit<F: FnOnce(&mut Self) -> Position>(&mut self, at: &Position, edit: F) -> Position {
+        let before = self.snapshot();
+        let depth = self.undo_stack.len();
+        self.grouping = self.grouping.saturating_add(1);
+        let end = edit(self);
+        self.grouping = self.grouping.saturating_sub(1);
+        self.undo_stack.truncate(depth);
+        self.push_undo_group(UndoGroup {
+            before,
+            start: at.clone(),
+            last: at.clone(),
+            last_time: None,
+        });
+        end
+    }
+
+    /// Reverts the last group of edits and returns where the first of them
+    /// happened, to move the cursor there
+    pub fn undo(&mut self) -> Option<Position> {
+        let group = self.undo_stack.pop_back()?;
+        self.redo_stack.push((self.snapshot(), group.start.clone()));
+        self.restore(group.before);
+        Some(group.start)
+    }
+
+    /// Reapplies the last group of edits reverted by `undo` and returns where
+    /// the first of them happened
+    pub fn redo(&mut self) -> Option<Position> {
+        let (after, start) = self.redo_stack.pop()?;
+        let before = self.snapshot();
+        self.restore(after);
+        self.push_undo_group(UndoGroup {
+            before,
+            start: start.clone(),
+            last: start.clone(),
+            last_time: None,
+        });
+        Some(start)
+    }
+
+    /// Sets whether `insert_indent` inserts tabs or spaces. Existing
+    /// indentation is left as it is, so saving does not convert it
+    pub fn set_indent_style(&mut self, indent_style: IndentStyle) {
+        self.indent_style = indent_style;
+    }
+
+    /// Returns the indexes of the rows whose indentation does not follow
+    /// `expected`, e.g. to warn about them in the gutter. With spaces, the
+    /// indentation must have no tabs and a multiple of the tab width of
+    /// spaces. With tabs, it must be tabs, only followed by fewer spaces
+    /// than the tab width for alignment. Blank rows are not checked.
+    #[must_use]
+    pub fn indentation_violations(&self, expected: IndentStyle) -> Vec<usize> {
+        let unit = self.tab_width;
+        let conforms = |indent: &str| match expected {
+            IndentStyle::Spaces => !indent.contains('\t') && indent.len().checked_rem(unit) == Some(0),
+            IndentStyle::Tabs => {
+                let spaces = indent.trim_start_matches('\t');
+                !spaces.contains('\t') && spaces.len() < unit
+            }
+        };
+        self.rows
+            .iter()
+            .enumerate()
+            .filter(|&(_, row)| !row.is_blank())
+            .filter(|&(_, row)| {
+                let text = row.as_str();
+                let indent = text.get(..text.len().saturating_sub(text.trim_start_matches([' ', '\t']).len()));
+                !indent.is_some_and(conforms)
+            })
+            .map(|(y, _)| y)
+            .collect()
+    }
+
+    /// Sets the distance between tab stops, used when indenting with spaces
+    /// and when displaying tabs. Tabs are only expanded for display, the
+    /// rows keep them as they are
+    pub fn set_tab_width(&mut self, tab_width: usize) {
+        self.tab_width = tab_width.max(1);
+        self.count_line_widths();
+    }
+
+    /// Sets how control characters other than tabs are displayed, which
+    /// also decides how many columns they take
+    pub fn set_control_char_display(&mut self, control_chars: ControlCharDisplay) {
+        self.control_chars = control_chars;
+        self.count_line_widths();
+    }
+
+    /// Returns how control characters other than tabs are displayed, e.g. to
+    /// render the rows
+    #[must_use]
+    pub fn control_char_display(&self) -> ControlCharDisplay {
+        self.control_chars
+    }
+
+    /// Returns the distance between tab stops, e.g. to render the rows
+    #[must_use]
+    pub fn tab_width(&self) -> usize {
+        self.tab_width
+    }
+
+    /// Returns the column at which `at` is displayed, see
+    /// `Row::visual_column`, e.g. to place the cursor on the screen
+    #[must_use]
+    pub fn visual_column(&self, at: &Position) -> usize {
+        self.rows
+            .get(at.y)
+            .map_or(0, |row| row.visual_column(at.x, self.tab_width, self.control_chars))
+    }
+
+    /// Returns the greatest display width among the rows, with tabs expanded,
+    /// e.g. to bound horizontal scrolling
+    #[must_use]
+    pub fn max_line_width(&self) -> usize {
+        self.line_widths.keys().next_back().copied().unwrap_or(0)
+    }
+
+    /// Returns the first row to show in a viewport of `viewport_height` rows
+    /// currently starting at `viewport_top`, so that `cursor_y` stays at least
+    /// `scrolloff` rows away from its top and bottom edges. The viewport does
+    /// not scroll past the last row, but always keeps the cursor visible.
+    #[must_use]
+    pub fn scroll_offset(&self, cursor_y: usize, viewport_top: usize, viewport_height: usize, scrolloff: usize) -> usize {
+        if viewport_height == 0 {
+            return cursor_y;
+        }
+        #[allow(clippy::integer_division_remainder_used)]
+        let scrolloff = scrolloff.min(viewport_height.saturating_sub(1) / 2);
+        let mut top = viewport_top;
+        if cursor_y < top.saturating_add(scrolloff) {
+            top = cursor_y.saturating_sub(scrolloff);
+        } else if cursor_y.saturating_add(scrolloff) >= top.saturating_add(viewport_height) {
+            top = cursor_y
+                .saturating_add(scrolloff)
+                .saturating_add(1)
+                .saturating_sub(viewport_height);
+        }
+        top.min(self.rows.len().saturating_sub(viewport_height))
+            .max(cursor_y.saturating_add(1).saturating_sub(viewport_height))
+    }
+
+    /// Returns the index of the grapheme of the row `y` displayed at the
+    /// 0-based `display_col`, e.g. for a mouse click, clamped to the length of
+    /// the row. It is the inverse of `visual_column`.
+    #[must_use]
+    pub fn column_to_x(&self, y: usize, display_col: usize) -> usize {
+        self.rows
+            .get(y)
+            .map_or(0, |row| row.x_at_column(display_col, self.tab_width, self.control_chars))
+    }
+
+    /// Returns the grapheme ranges, end excluded, of the segments the row `y`
+    /// is displayed in with soft wrap at `width` columns, or none if there is
+    /// no such row
+    #[must_use]
+    pub fn wrap_row(&self, y: usize, width: usize) -> Vec<(usize, usize)> {
+        self.rows
+            .get(y)
+            .map_or_else(Vec::new, |row| row.wrap(width, self.tab_width, self.control_chars))
+    }
+
+    /// Returns the number of lines the document is displayed on with soft
+    /// wrap at `width` columns, e.g. to size a scrollbar
+    #[must_use]
+    pub fn visual_line_count(&self, width: usize) -> usize {
+        (0..self.rows.len()).map(|y| self.wrap_row(y, width).len()).sum()
+    }
+
+    /// Returns the display columns at which to draw indent guides on the row
+    /// `y`, one per tab stop within its indentation. A blank row continues
+    /// the guides of its block, taking the smaller indentation of the
+    /// nearest non-blank rows above and below it.
+    #[must_use]
+    pub fn indent_guides(&self, y: usize) -> Vec<usize> {
+        let width = match self.rows.get(y) {
+            None => return Vec::new(),
+            Some(row) if !row.is_blank() => self.indent_width(row),
+            Some(_) => {
+                let above = self.rows.get(..y).and_then(|rows| rows.iter().rev().find(|row| !row.is_blank()));
+                let below = self
+                    .rows
+                    .get(y.saturating_add(1)..)
+                    .and_then(|rows| rows.iter().find(|row| !row.is_blank()));
+                match (above, below) {
+                    (Some(above), Some(below)) => self.indent_width(above).min(self.indent_width(below)),
+                    _ => 0,
+                }
+            }
+        };
+        (0..width).step_by(self.tab_width).collect()
+    }
+
+    /// Returns the display width of the leading whitespace of `row`
+    fn indent_width(&self, row: &Row) -> usize {
+        let indent = row.as_str().chars().take_while(|c| *c == ' ' || *c == '\t').count();
+        row.visual_column(indent, self.tab_width, self.control_chars)
+    }
+
+    /// Returns the display width of the row `y`
+    fn line_width(&self, y: usize) -> Option<usize> {
+        self.rows
+            .get(y)
+            .map(|row| row.visual_column(row.len(), self.tab_width, self.control_chars))
+    }
+
+    fn add_line_width(&mut self, width: usize) {
+        let count = self.line_widths.entry(width).or_insert(0);
+        *count = count.saturating_add(1);
+    }
+
+    fn remove_line_width(&mut self, width: usize) {
+        if let Some(count) = self.line_widths.get_mut(&width) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                self.line_widths.remove(&width);
+            }
+        }
+    }
+
+    /// Replaces `old_width`, if any, with the current width of the row `y`
+    /// in the widths counted by `max_line_width`
+    fn update_line_width(&mut self, y: usize, old_width: Option<usize>) {
+        if let Some(old_width) = old_width {
+            self.remove_line_width(old_width);
+        }
+        if let Some(width) = self.line_width(y) {
+            self.add_line_width(width);
+        }
+    }
+
+    /// Counts the widths of all the rows from scratch
+    fn count_line_widths(&mut self) {
+        self.line_widths.clear();
+        for y in 0..self.rows.len() {
+            self.update_line_width(y, None);
+        }
+    }
+
+    /// Inserts one level of indentation at the given position and returns
+    /// the position right after it. With `IndentStyle::Spaces` it inserts as
+    /// many spaces as needed to reach the next tab stop.
+    pub fn insert_indent(&mut self, at: &Position) -> Position {
+        match self.indent_style {
+            IndentStyle::Tabs => self.insert_str(at, "\t"),
+            IndentStyle::Spaces => {
+                let column = self.visual_column(at).checked_rem(self.tab_width).unwrap_or(0);
+                let spaces = " ".repeat(self.tab_width.saturating_sub(column));
+                self.insert_str(at, &spaces)
+            }
+        }
+    }
+
+    /// Removes one level of indentation right before the given position and
+    /// returns the position where the cursor should move. It only removes a
+    /// tab or the spaces back to the previous tab stop, and only if everything
+    /// before `at` on the row is whitespace.
+    pub fn remove_indent(&mut self, at: &Position) -> Position {
+        let Some(row) = self.rows.get(at.y) else {
+            return at.clone();
+        };
+        let before = row.slice(0, at.x);
+        if !before.chars().all(char::is_whitespace) {
+            return at.clone();
+        }
+        let count = if before.ends_with('\t') {
+            1
+        } else {
+            let column = row
+                .visual_column(at.x, self.tab_width, self.control_chars)
+                .checked_rem(self.tab_width)
+                .unwrap_or(0);
+            let unit = if column == 0 { self.tab_width } else { column };
+            before.chars().rev().take(unit).take_while(|c| *c == ' ').count()
+        };
+        let start = Position {
+            x: at.x.saturating_sub(count),
+            y: at.y,
+        };
+        self.remove(&start, at);
+        start
+    }
+
+    /// Sets whether `split_line` copies the indentation of the row it splits
+    pub fn set_auto_indent(&mut self, auto_indent: bool) {
+        self.auto_indent = auto_indent;
+    }
+
+    /// Sets whether `yank` and `yank_pop` adjust the indentation of the
+    /// pasted text with `insert_block_reindented`
+    pub fn set_reindent_on_paste(&mut self, reindent_on_paste: bool) {
+        self.reindent_on_paste = reindent_on_paste;
+    }
+
+    /// Inserts a block of text at the given position after replacing the
+    /// indentation its lines have in common with the indentation of the row
+    /// at `at`, and returns the position right after it.
+    pub fn insert_block_reindented(&mut self, at: &Position, text: &str) -> Position {
+        let leading = |line: &str| -> usize { line.chars().take_while(|c| *c == ' ' || *c == '\t').count() };
+        let common = text
+            .split('\n')
+            .filter(|line| !line.trim().is_empty())
+            .map(leading)
+            .min()
+            .unwrap_or(0);
+        let indent = self.rows.get(at.y).map_or_else(String::new, |row| {
+            let before = row.slice(0, at.x);
+            let len = leading(&before);
+            before.chars().take(len).collect()
+        });
+        let block = text
+            .split('\n')
+            .enumerate()
+            .map(|(index, line)| {
+                let line: String = line.chars().skip(common.min(leading(line))).collect();
+                if index == 0 || line.is_empty() {
+                    line
+                } else {
+                    format!("{indent}{line}")
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.insert_str(at, &block)
+    }
+
+    /// Sets how many kills the kill ring remembers, dropping the oldest ones
+    /// if it already holds more.
+    pub fn set_kill_ring_size(&mut self, size: usize) {
+        self.kill_ring_size = size;
+        self.kill_ring.truncate(size);
+    }
+
+    /// Removes the text between `start` and `end` and pushes it onto the
+    /// kill ring.
+    pub fn cut(&mut self, start: &Position, end: &Position) -> String {
+        let text = self.remove(start, end);
+        if !text.is_empty() && self.kill_ring_size > 0 {
+            self.kill_ring.push_front(text.clone());
+            self.kill_ring.truncate(self.kill_ring_size);
+        }
+        text
+    }
+
+    /// Cuts from the given position until the end of the next word.
+    pub fn delete_word(&mut self, at: &Position) -> String {
+        if let Some(row) = self.rows.get(at.y) {
+            let end = if at.x < row.len() {
+                Position { x: row.word_end(at.x, self.word_chars), y: at.y }
+            } else {
+                Position { x: 0, y: at.y.saturating_add(1) }
+            };
+            return self.cut(at, &end);
+        }
+        String::new()
+    }
+
+    /// Cuts from the given position until the end of the row, or the line
+    /// break itself if the position is already at the end of the row.
+    pub fn kill_line(&mut self, at: &Position) -> String {
+        if let Some(row) = self.rows.get(at.y) {
+            let end = if at.x < row.len() {
+                Position { x: row.len(), y: at.y }
+            } else {
+                Position { x: 0, y: at.y.saturating_add(1) }
+            };
+            return self.cut(at, &end);
+        }
+        String::new()
+    }
+
+    /// Pastes the last kill at the given position and returns the position
+    /// right after it.
+    pub fn yank(&mut self, at: &Position) -> Option<Position> {
+        let text = self.kill_ring.front()?.clone();
+        let end = self.insert_yanked(at, &text);
+        self.last_yank = Some(Yank {
+            start: at.clone(),
+            end: end.clone(),
+            index: 0,
+        });
+        Some(end)
+    }
+
+    /// Inserts `text` pasted from the kill ring at `at`, reindented if
+    /// `reindent_on_paste` is on, and returns the position right after it
+    fn insert_yanked(&mut self, at: &Position, text: &str) -> Position {
+        if self.reindent_on_paste {
+            self.insert_block_reindented(at, text)
+        } else {
+            self.insert_str(at, text)
+        }
+    }
+
+    /// Replaces the text pasted by the last `yank` or `yank_pop` with the
+    /// previous kill in the ring and returns the position right after it.
+    /// It does nothing if the document was edited since the last yank.
+    pub fn yank_pop(&mut self) -> Option<Position> {
+        let yank = self.last_yank.take()?;
+        let index = yank.index.saturating_add(1).checked_rem(self.kill_ring.len())?;
+        let text = self.kill_ring.get(index)?.clone();
+        self.remove(&yank.start, &yank.end);
+        let end = self.insert_yanked(&yank.start, &text);
+        self.last_yank = Some(Yank {
+            start: yank.start,
+            end: end.clone(),
+            index,
+        });
+        Some(end)
+    }
+
+    /// Extends `start` back to the start of its word and `end`, which is
+    /// excluded from the selection, forward to the end of its word. Ends that
+    /// are not inside a word are left as they are.
+    #[must_use]
+    pub fn expand_to_word_boundaries(&self, start: &Position, end: &Position) -> (Position, Position) {
+        let start_x = self
+            .rows
+            .get(start.y)
+            .and_then(|row| row.word_bounds(start.x, self.word_chars))
+            .map_or(start.x, |(word_start, _)| word_start);
+        let end_x = end
+            .x
+            .checked_sub(1)
+            .and_then(|x| self.rows.get(end.y)?.word_bounds(x, self.word_chars))
+            .map_or(end.x, |(_, word_end)| word_end);
+        (
+            Position { x: start_x, y: start.y },
+            Position { x: end_x, y: end.y },
+        )
+    }
+
+    /// Returns the text between `start` and `end`, joining rows with `\n`.
+    fn text(&self, start: &Position, end: &Position) -> String {
+        let mut text = String::new();
+        for y in start.y..=end.y {
+            if let Some(row) = self.rows.get(y) {
+                let from = if y == start.y { start.x } else { 0 };
+                let to = if y == end.y { end.x } else { row.len() };
+                text.push_str(&row.slice(from, to));
+                if y < end.y {
+                    text.push('\n');
+                }
+            }
+        }
+        text
+    }
+
+    /// Removes the text between `start` and `end` and returns it.
+    fn remove(&mut self, start: &Position, end: &Position) -> String {
+        let text = self.text(start, end);
+        for _ in text.graphemes(true) {
+            self.delete(start);
+        }
+        text
+    }
+
+    /// Remembers `query` as the most recent search, unless it is empty or the
+    /// same as the previous one, and starts recalling from it again
+    pub fn push_search(&mut self, query: &str) {
+        self.search_history_index = None;
+        if query.is_empty() || self.search_history.front().is_some_and(|last| last == query) {
+            return;
+        }
+        self.search_history.push_front(query.to_owned());
+        self.search_history.truncate(SEARCH_HISTORY_SIZE);
+    }
+
+    /// Recalls the search before the one being recalled, starting with the
+    /// most recent one, or returns `None` past the oldest one
+    pub fn history_prev(&mut self) -> Option<&str> {
+        let index = self.search_history_index.map_or(0, |index| index.saturating_add(1));
+        if index >= self.search_history.len() {
+            return None;
+        }
+        self.search_history_index = Some(index);
+        self.search_history.get(index).map(String::as_str)
+    }
+
+    /// Recalls the search after the one being recalled, or returns `None`
+    /// past the most recent one
+    pub fn history_next(&mut self) -> Option<&str> {
+        let index = self.search_history_index?.checked_sub(1);
+        self.search_history_index = index;
+        self.search_history.get(index?).map(String::as_str)
+    }
+
+    /// Saves the changes in the document and returns the number of bytes
+    /// written, line endings included. Nothing is written if the document
+    /// has no `file_name`.
+    ///
+    /// An existing file is rewritten in place and keeps its owner and its
+    /// permissions, a new one gets the default permissions of the process.
+    ///
+    /// # Errors
+    ///
+    /// It will return `Err` if `file_name` does not exist or the user
+    /// does not have the permission to write to it
+    pub fn save(&mut self) -> Result<usize, Error> {
+        let mut written = 0;
+        if self.file_name.is_some() {
+            self.restore_trailing_whitespace();
+            if let Some(column) = self.hard_wrap_column.filter(|_| self.file_type.is_prose()) {
+                self.hard_wrap(column);
+            }
+        }
+        if let Some(file_name) = self.file_name.as_deref() {
+            let mut file = fs::File::create(file_name)?;
+            written = usize::try_from(self.write_rows(&mut file, 0)?).map_err(Error::other)?;
+            self.redetect_file_type();
+            self.dirty = false;
+            self.lossy = false;
+            self.take_snapshot();
+        }
+        Ok(written)
+    }
+
+    /// Saves the changes in the document, rewriting only the rows from the
+    /// first one that changed since the last open or save.
+    ///
+    /// It falls back to a full `save` when the file on disk no longer matches
+    /// the snapshot taken at the last open or save.
+    ///
+    /// # Errors
+    ///
+    /// It will return `Err` if `file_name` does not exist or the user
+    /// does not have the permission to write to it
+    pub fn save_incremental(&mut self) -> Result<(), Error> {
+        if self.file_name.is_some() {
+            self.restore_trailing_whitespace();
+            if let Some(column) = self.hard_wrap_column.filter(|_| self.file_type.is_prose()) {
+                self.hard_wrap(column);
+            }
+        }
+        let file = if let Some(file_name) = self.file_name.as_deref() {
+            fs::OpenOptions::new().read(true).write(true).open(file_name)
+        } else {
+            return Ok(());
+        };
+        let Ok(mut file) = file else {
+            self.save()?;
+            return Ok(());
+        };
+        if let Some(end) = self.write_incremental(&mut file)? {
+            file.set_len(end)?;
+            self.redetect_file_type();
+            self.dirty = false;
+            self.lossy = false;
+            self.take_snapshot();
+            Ok(())
+        } else {
+            self.save()?;
+            Ok(())
+        }
+    }
+
+    /// Sets whether saving keeps the trailing whitespace that rows had at
+    /// the last open or save when only their trailing whitespace changed,
+    /// so that the file on disk only differs on the rows really edited
+    pub fn set_minimize_whitespace_churn(&mut self, minimize_whitespace_churn: bool) {
+        self.minimize_whitespace_churn = minimize_whitespace_churn;
+    }
+
+    /// Sets whether saving detects the file type from the content, e.g. the
+    /// shebang of a script without an extension, as well as from the file
+    /// name. It is off by default.
+    pub fn set_detect_type_from_content(&mut self, detect_type_from_content: bool) {
+        self.detect_type_from_content = detect_type_from_content;
+    }
+
+    /// Detects the file type again from the file name, and the first row if
+    /// enabled, after a save, and highlights the document again if it changed.
+    /// A file type chosen with `set_file_type` is kept.
+    fn redetect_file_type(&mut self) {
+        let Some(file_name) = self.file_name.as_ref().filter(|_| !self.file_type_set) else {
+            return;
+        };
+        let first_line = self.rows.first().map(|row| row.as_str()).filter(|_| self.detect_type_from_content);
+        let file_type = FileType::detect(file_name, first_line);
+        if file_type.name() != self.file_type.name() {
+            self.file_type = file_type;
+            self.rehighlight_all();
+        }
+    }
+
+    /// Sets the column at which rows are hard-wrapped on save, see
+    /// `hard_wrap`. Only plain text and Markdown are wrapped, code is left
+    /// as it is.
+    pub fn set_hard_wrap_column(&mut self, hard_wrap_column: Option<usize>) {
+        self.hard_wrap_column = hard_wrap_column;
+    }
+
+    /// Splits the rows wider than `column` columns at the last space that
+    /// keeps them within it, as a single undo step. The new rows keep the
+    /// indentation of the row they come from. A word longer than the width
+    /// is only split from the text around it.
+    pub fn hard_wrap(&mut self, column: usize) {
+        let column = column.max(1);
+        if !(0..self.rows.len()).any(|y| self.hard_wrap_point(y, column).is_some()) {
+            return;
+        }
+        self.as_one_edit(&Position::default(), |document| {
+            let mut y: usize = 0;
+            while y < document.rows.len() {
+                if let Some((start, end, indent)) = document.hard_wrap_point(y, column) {
+                    let at = Position { x: start, y };
+                    for _ in start..end {
+                        document.delete(&at);
+                    }
+                    document.insert(&at, '\n');
+                    document.insert_str(&Position { x: 0, y: y.saturating_add(1) }, &indent);
+                }
+                y = y.saturating_add(1);
+            }
+            Position::default()
+        });
+    }
+
+    /// Returns the run of spaces at which to split the row `y` so that it
+    /// fits in `column` columns, with the indentation of the row, or `None`
+    /// if the row fits or has nowhere to be split
+    fn hard_wrap_point(&self, y: usize, column: usize) -> Option<(usize, usize, String)> {
+        let row = self.rows.get(y)?;
+        if row.visual_column(row.len(), self.tab_width, self.control_chars) <= column {
+            return None;
+        }
+        let graphemes: Vec<&str> = row.as_str().graphemes(true).collect();
+        let indent = graphemes.iter().take_while(|grapheme| matches!(**grapheme, " " | "\t")).count();
+        let mut split = None;
+        let mut x = indent;
+        while x < graphemes.len() {
+            if graphemes.get(x) != Some(&" ") {
+                x = x.saturating_add(1);
+                continue;
+            }
+            let end = graphemes
+                .iter()
+                .skip(x)
+                .position(|grapheme| *grapheme != " ")
+                .map_or(graphemes.len(), |offset| x.saturating_add(offset));
+            if end == graphemes.len() {
+                break;
+            }
+            if split.is_none() || row.visual_column(x, self.tab_width, self.control_chars) <= column {
+                split = Some((x, end));
+            }
+            if row.visual_column(x, self.tab_width, self.control_chars) >= column {
+                break;
+            }
+            x = end;
+        }
+        let (start, end) = split?;
+        Some((start, end, row.slice(0, indent)))
+    }
+
+    /// Puts back the saved version of each row that only differs from it by
+    /// trailing whitespace, if `minimize_whitespace_churn` is set. Rows are
+    /// matched with the saved ones counting both from the start and from
+    /// the end of the document, so that rows inserted or removed elsewhere
+    /// do not prevent it.
+    fn restore_trailing_whitespace(&mut self) {
+        if !self.minimize_whitespace_churn {
+            return;
+        }
+        let (len, saved_len) = (self.rows.len(), self.saved_rows.len());
+        for y in 0..len {
+            let Some(row) = self.rows.get(y) else {
+                break;
+            };
+            let from_end = y.saturating_add(saved_len).checked_sub(len);
+            let saved = [Some(y), from_end]
+                .into_iter()
+                .flatten()
+                .filter_map(|index| self.saved_rows.get(index))
+                .find(|saved| saved.as_str() != row.as_str() && saved.as_str().trim_end() == row.as_str().trim_end())
+                .map(Rc::clone);
+            if let Some(saved) = saved {
+                let old_width = self.line_width(y);
+                if let Some(slot) = self.rows.get_mut(y) {
+                    *slot = saved;
+                }
+                self.update_line_width(y, old_width);
+                self.unhighlight_rows(y);
+            }
+        }
+    }
+
+    /// Rewrites `file` from the first row that differs from the snapshot and
+    /// returns the new end of the file, or `None` if the unchanged prefix on
+    /// disk can not be verified, e.g. because the line ending changed since,
+    /// and a full save is needed.
+    fn write_incremental<F: Read + Write + Seek>(&self, file: &mut F) -> Result<Option<u64>, Error> {
+        if self.line_ending != self.saved_line_ending || file.seek(SeekFrom::End(0))? != self.saved_len {
+            return Ok(None);
+        }
+        let first_changed = self
+            .rows
+            .iter()
+            .zip(&self.saved_hashes)
+            .position(|(row, hash)| hash_bytes(row.as_bytes()) != *hash)
+            .unwrap_or_else(|| self.rows.len().min(self.saved_hashes.len()));
+        if first_changed == 0 {
+            return Ok(None);
+        }
+
+        let prefix_len: u64 = self
+            .rows
+            .iter()
+            .take(first_changed)
+            .fold(0, |len, row| len.saturating_add(row_len(row, self.line_ending)));
+        let mut prefix = Vec::new();
+        file.seek(SeekFrom::Start(0))?;
+        Read::take(&mut *file, prefix_len).read_to_end(&mut prefix)?;
+        let on_disk = prefix
+            .split(|byte| *byte == b'\n')
+            .map(|line| line.strip_suffix(b"\r").filter(|_| self.line_ending == LineEnding::CrLf).unwrap_or(line))
+            .take(first_changed);
+        if !on_disk.map(hash_bytes).eq(self.saved_hashes.iter().take(first_changed).copied()) {
+            return Ok(None);
+        }
+
+        file.seek(SeekFrom::Start(prefix_len))?;
+        let written = self.write_rows(file, first_changed)?;
+        Ok(Some(prefix_len.saturating_add(written)))
+    }
+
+    /// Writes the rows starting at `from` and returns the number of bytes
+    /// written.
+    fn write_rows<W: Write>(&self, writer: &mut W, from: usize) -> Result<u64, Error> {
+        let mut written: u64 = 0;
+        for row in self.rows.iter().skip(from) {
+            writer.write_all(row.as_bytes())?;
+            writer.write_all(self.line_ending.as_bytes())?;
+            written = written.saturating_add(row_len(row, self.line_ending));
+        }
+        Ok(written)
+    }
+
+    /// Remembers the rows as they are on disk, to be compared against by
+    /// `save_incremental`.
+    fn take_snapshot(&mut self) {
+        self.saved_hashes = self.rows.iter().map(|row| hash_bytes(row.as_bytes())).collect();
+        self.saved_rows.clone_from(&self.rows);
+        self.saved_line_ending = self.line_ending;
+        self.saved_len = self
+            .rows
+            .iter()
+            .fold(0, |len, row| len.saturating_add(row_len(row, self.line_ending)));
+    }
+
+    /// Loop over the rows and highligh the words that correspond
+    /// the word that was passed as a parameter.
+    ///
+    /// # Panics
+    ///
+    /// It will panic if the rows until `until` can not be indexed.
+    pub fn highlight(&mut self, word: &Option<String>, until: Option<usize>) {
+        let mut state = highlighting::HlState::Normal;
+        let until = if let Some(until) = until {
+            if until.saturating_add(1) < self.rows.len() {
+                until.saturating_add(1)
+            } else {
+                self.rows.len()
+            }
+        } else {
+            self.rows.len()
+        };
+
+        if word.is_some() {
+            self.highlighted_word.clone_from(word);
+        }
+        let opts = self.file_type.highlighting_options();
+        let mut depth = 0;
+        let rows_indexed = self.rows.get_mut(..until).expect("Failed while trying to index rows");
+        for row in rows_indexed {
+            state = if row.needs_highlight(word, state) {
+                Rc::make_mut(row).highlight(opts, word, state)
+            } else {
+                row.end_state()
+            };
+            if opts.brackets() {
+                let (end_depth, colored) = row.bracket_depth(depth);
+                if !colored {
+                    Rc::make_mut(row).highlight_brackets(depth);
+                }
+                depth = end_depth;
+            }
+        }
+    }
+
+    /// Highlights the occurrences of the word under `at`, or clears them if
+    /// `at` is not on a word. Nothing is recomputed while the word stays the
+    /// same, e.g. when the cursor moves within it. Returns whether the
+    /// document was highlighted again.
+    pub fn update_word_highlight(&mut self, at: &Position) -> bool {
+        let word = self
+            .rows
+            .get(at.y)
+            .and_then(|row| row.word_bounds(at.x, self.word_chars).map(|(start, end)| row.slice(start, end)));
+        if word == self.highlighted_word {
+            return false;
+        }
+        if word.is_some() {
+            self.highlight(&word, None);
+        } else {
+            self.rehighlight_all();
+        }
+        self.highlighted_word = word;
+        true
+    }
+
+    /// Changes the language of the document, e.g. to highlight a file without
+    /// extension as Rust, and highlights it again. The document is not
+    /// marked dirty since its text does not change, and saving keeps the
+    /// file type instead of detecting it again.
+    pub fn set_file_type(&mut self, file_type: FileType) {
+        self.file_type = file_type;
+        self.file_type_set = true;
+        self.rehighlight_all();
+    }
+
+    /// Sets the words, such as `TODO`, highlighted as annotations in the
+    /// comments of the current file type, and highlights the document again
+    pub fn set_annotations(&mut self, annotations: &[&str]) {
+        self.file_type.set_annotations(annotations);
+        self.rehighlight_all();
+    }
+
+    /// Highlights every row again from the first one, even those whose
+    /// highlighting is up to date, e.g. after the file type or the
+    /// highlighting options changed
+    pub fn rehighlight_all(&mut self) {
+        self.unhighlight_rows(0);
+        self.highlight(&None, None);
+    }
+
+    /// Returns the annotations, such as `TODO`, in the comments of the
+    /// document with the text of their row, e.g. to list them in a panel.
+    /// Each row gives at most one annotation, at the first keyword found in
+    /// it, as of the last call to `highlight`.
+    #[must_use]
+    pub fn annotations(&self) -> Vec<(Position, String)> {
+        let mut annotations = Vec::new();
+        for (y, row) in self.rows.iter().enumerate() {
+            let mut char_index: usize = 0;
+            for (x, grapheme) in row.as_str().graphemes(true).enumerate() {
+                if row.highlighting().get(char_index) == Some(&highlighting::Type::Annotation) {
+                    annotations.push((Position { x, y }, row.as_str().trim().to_owned()));
+                    break;
+                }
+                char_index = char_index.saturating_add(grapheme.chars().count());
+            }
+        }
+        annotations
+    }
+
+    /// Returns the style problems of the document with where they are, in
+    /// document order, e.g. to flag them in the gutter
+    #[must_use]
+    pub fn lint(&self) -> Vec<(Position, Lint)> {
+        let mut lints = Vec::new();
+        for (y, row) in self.rows.iter().enumerate() {
+            let mut graphemes = row.as_str().graphemes(true).enumerate();
+            if graphemes.by_ref().find(|&(_, grapheme)| !matches!(grapheme, " " | "\t")).is_none() {
+                continue;
+            }
+            lints.extend(
+                graphemes
+                    .filter(|&(_, grapheme)| grapheme == "\t")
+                    .map(|(x, _)| (Position { x, y }, Lint::TabForAlignment)),
+            );
+        }
+        lints
+    }
+
+    /// Returns the positions, in document order, of the brackets and braces
+    /// that have no matching counterpart. Those in strings and comments are
+    /// skipped, as of the last call to `highlight`.
+    #[must_use]
+    pub fn unbalanced_brackets(&self) -> Vec<Position> {
+        let mut open: Vec<(&str, Position)> = Vec::new();
+        let mut unbalanced = Vec::new();
+        for (y, row) in self.rows.iter().enumerate() {
+            for (x, (grapheme, is_code)) in code_graphemes(row).into_iter().enumerate() {
+                if !is_code {
+                    continue;
+                }
+                let opening = match grapheme {
+                    "(" | "[" | "{" => {
+                        open.push((grapheme, Position { x, y }));
+                        continue;
+                    }
+                    ")" => "(",
+                    "]" => "[",
+                    "}" => "{",
+                    _ => continue,
+                };
+                if open.last().is_some_and(|&(bracket, _)| bracket == opening) {
+                    open.pop();
+                } else {
+                    unbalanced.push(Position { x, y });
+                }
+            }
+        }
+        unbalanced.extend(open.into_iter().map(|(_, position)| position));
+        unbalanced.sort_by_key(|position| (position.y, position.x));
+        unbalanced
+    }
+
+    /// Returns the position of the bracket or brace matching the one at `at`,
+    /// if `at` is on one. Those in strings and comments are skipped, as of
+    /// the last call to `highlight`.
+    #[must_use]
+    pub fn matching_bracket(&self, at: &Position) -> Option<Position> {
+        let (bracket, at_code) = *code_graphemes(self.rows.get(at.y)?).get(at.x)?;
+        let (open, close, forward) = match bracket {
+            "(" | ")" => ("(", ")", bracket == "("),
+            "[" | "]" => ("[", "]", bracket == "["),
+            "{" | "}" => ("{", "}", bracket == "{"),
+            _ => return None,
+        };
+        if !at_code {
+            return None;
+        }
+        let (inward, outward) = if forward { (open, close) } else { (close, open) };
+        let mut depth: usize = 0;
+        let rows: Vec<usize> = if forward {
+            (at.y..self.rows.len()).collect()
+        } else {
+            (0..=at.y).rev().collect()
+        };
+        for y in rows {
+            let Some(row) = self.rows.get(y) else {
+                break;
+            };
+            let mut cells: Vec<(usize, (&str, bool))> = code_graphemes(row).into_iter().enumerate().collect();
+            if y == at.y {
+                if forward {
+                    cells.drain(..at.x);
+                } else {
+                    cells.truncate(at.x.saturating_add(1));
+                }
+            }
+            if !forward {
+                cells.reverse();
+            }
+            for (x, (grapheme, is_code)) in cells {
+                if !is_code {
+                    continue;
+                }
+                if grapheme == inward {
+                    depth = depth.saturating_add(1);
+                } else if grapheme == outward {
+                    depth = depth.saturating_sub(1);
+                    if depth == 0 {
+                        return Some(Position { x, y });
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Marks the bracket at `at` and the one matching it, if any, with the
+    /// `MatchingBracket` highlighting over their syntax colors, after
+    /// clearing the previous ones, see `clear_bracket_highlight`
+    pub fn bracket_match_highlight(&mut self, at: &Position) {
+        self.clear_bracket_highlight();
+        let Some(other) = self.matching_bracket(at) else {
+            return;
+        };
+        for position in [at.clone(), other] {
+            let previous = self
+                .rows
+                .get_mut(position.y)
+                .and_then(|row| Rc::make_mut(row).set_highlight(position.x, highlighting::Type::MatchingBracket));
+            if let Some(previous) = previous {
+                self.bracket_highlight.push((position, previous));
+            }
+        }
+    }
+
+    /// Gives the brackets marked by `bracket_match_highlight` their syntax
+    /// colors back
+    pub fn clear_bracket_highlight(&mut self) {
+        for (position, previous) in mem::take(&mut self.bracket_highlight) {
+            if let Some(row) = self.rows.get_mut(position.y) {
+                if row.highlight_at(position.x) == Some(highlighting::Type::MatchingBracket) {
+                    Rc::make_mut(row).set_highlight(position.x, previous);
+                }
+            }
+        }
+    }
+
+    /// Counts the characters of each highlighting type across the document,
+    /// e.g. to know how much of a file is comments. Rows that were not
+    /// highlighted yet are not counted.
+    #[must_use]
+    pub fn highlight_breakdown(&self) -> HashMap<highlighting::Type, usize> {
+        let mut breakdown: HashMap<highlighting::Type, usize> = HashMap::new();
+        for hl_type in self.rows.iter().flat_map(|row| row.highlighting()) {
+            let count = breakdown.entry(*hl_type).or_insert(0);
+            *count = count.saturating_add(1);
+        }
+        breakdown
+    }
+
+    /// Returns the position of the blank line that ends the paragraph at
+    /// `at` in the given direction, or the start/end of the document if there
+    /// is none. Runs of blank lines count as a single separator.
+    #[must_use]
+    pub fn next_paragraph(&self, at: &Position, direction: VerticalDirection) -> Position {
+        let is_blank = |y: usize| self.rows.get(y).is_some_and(|row| row.is_blank());
+        let mut y = at.y;
+        match direction {
+            VerticalDirection::Down => {
+                let last = self.rows.len().saturating_sub(1);
+                while y < last && is_blank(y) {
+                    y = y.saturating_add(1);
+                }
+                while y <= last && !is_blank(y) {
+                    y = y.saturating_add(1);
+                }
+                if y <= last {
+                    return Position { x: 0, y };
+                }
+                Position {
+                    x: self.rows.get(last).map_or(0, |row| row.len()),
+                    y: last,
+                }
+            }
+            VerticalDirection::Up => {
+                while y > 0 && is_blank(y) {
+                    y = y.saturating_sub(1);
+                }
+                while y > 0 && !is_blank(y) {
+                    y = y.saturating_sub(1);
+                }
+                Position { x: 0, y }
+            }
+        }
+    }
+
+    /// Returns how many blank rows, empty or holding only whitespace, the
+    /// document starts with
+    #[must_use]
+    pub fn leading_blank_lines(&self) -> usize {
+        self.rows.iter().take_while(|row| row.is_blank()).count()
+    }
+
+    /// Returns how many blank rows, empty or holding only whitespace, the
+    /// document ends with
+    #[must_use]
+    pub fn trailing_blank_lines(&self) -> usize {
+        self.rows.iter().rev().take_while(|row| row.is_blank()).count()
+    }
+
+    /// Returns the start and end of the paragraph around `at`, from the
+    /// start of its first row to the end of its last row. On a blank line
+    /// both positions are the start of that line.
+    #[must_use]
+    pub fn paragraph_bounds(&self, at: &Position) -> (Position, Position) {
+        let is_blank = |y: usize| self.rows.get(y).is_none_or(|row| row.is_blank());
+        if is_blank(at.y) {
+            return (Position { x: 0, y: at.y }, Position { x: 0, y: at.y });
+        }
+        let mut start = at.y;
+        while start > 0 && !is_blank(start.saturating_sub(1)) {
+            start = start.saturating_sub(1);
+        }
+        let mut end = at.y;
+        while !is_blank(end.saturating_add(1)) {
+            end = end.saturating_add(1);
+        }
+        (
+            Position { x: 0, y: start },
+            Position {
+                x: self.rows.get(end).map_or(0, |row| row.len()),
+                y: end,
+            },
+        )
+    }
+
+    /// Returns the first and last rows and the text, joined with `\n`, of the
+    /// run of comment rows right above the row `y`, e.g. the documentation of
+    /// an item. Rows that were not highlighted yet do not count as comments.
+    #[must_use]
+    pub fn leading_comment_block(&self, y: usize) -> Option<(usize, usize, String)> {
+        let end = y.checked_sub(1)?;
+        let is_comment = |index: usize| self.rows.get(index).is_some_and(|row| row.is_comment());
+        if !is_comment(end) {
+            return None;
+        }
+        let mut start = end;
+        while start > 0 && is_comment(start.saturating_sub(1)) {
+            start = start.saturating_sub(1);
+        }
+        let text = self
+            .rows
+            .get(start..=end)?
+            .iter()
+            .map(|row| row.as_str().trim())
+            .collect::<Vec<_>>()
+            .join("\n");
+        Some((start, end, text))
+    }
+
+    /// Rewraps the paragraph around `at` so that its rows are no wider than
+    /// `width` columns, keeping the indentation of its first row. A word
+    /// longer than `width` gets a row of its own. It is undone in one step.
+    pub fn reflow_paragraph(&mut self, at: &Position, width: usize) {
+        let (start, end) = self.paragraph_bounds(at);
+        if start == end {
+            return;
+        }
+        let text = self.text(&start, &end);
+        let indent: String = text.chars().take_while(|c| *c == ' ' || *c == '\t').collect();
+        let indent_width = Row::from(indent.as_str()).visual_column(indent.chars().count(), self.tab_width, self.control_chars);
+
+        let mut lines: Vec<String> = Vec::new();
+        let mut line = indent.clone();
+        let mut line_width = indent_width;
+        for word in text.split_whitespace() {
+            let word_row = Row::from(word);
+            let word_width = word_row.visual_column(word_row.len(), self.tab_width, self.control_chars);
+            if line_width > indent_width {
+                if line_width.saturating_add(1).saturating_add(word_width) > width {
+                    lines.push(mem::replace(&mut line, indent.clone()));
+                    line_width = indent_width;
+                } else {
+                    line.push(' ');
+                    line_width = line_width.saturating_add(1);
+                }
+            }
+            line.push_str(word);
+            line_width = line_width.saturating_add(word_width);
+        }
+        lines.push(line);
+        self.replace_range(&start, &end, &lines.join("\n"));
+    }
+
+    /// Returns the grapheme ranges, end excluded, of the matches of `query`
+    /// on the row `y`, e.g. to draw search results over it
+    #[must_use]
+    pub fn match_ranges_in_row(&self, y: usize, query: &str, case_sensitive: bool) -> Vec<(usize, usize)> {
+        self.rows
+            .get(y)
+            .map_or_else(Vec::new, |row| row.match_ranges(query, case_sensitive))
+    }
+
+    /// Returns every match of `query` with the text of its row, e.g. to list
+    /// them in a results panel
+    #[must_use]
+    pub fn search_results(&self, query: &str, case_sensitive: bool) -> Vec<SearchResult> {
+        let mut results = Vec::new();
+        for (y, row) in self.rows.iter().enumerate() {
+            for (start, end) in row.match_ranges(query, case_sensitive) {
+                results.push(SearchResult {
+                    position: Position { x: start, y },
+                    row_text: row.as_str().to_owned(),
+                    range: (start, end),
+                });
+            }
+        }
+        results
+    }
+
+    /// Returns the position of the byte at `offset` in the document as it is
+    /// saved, each row followed by the line ending, e.g. for an offset
+    /// reported by a compiler or `grep -b`. An offset in the middle of a
+    /// grapheme gives that grapheme, one in a line ending gives the end of
+    /// the row, and one right at the end of the file the start of the line
+    /// after the last row. It returns `None` past the end of the file.
+    #[must_use]
+    pub fn byte_offset_to_position(&self, offset: usize) -> Option<Position> {
+        let ending_len = self.line_ending.as_bytes().len();
+        let mut row_start: usize = 0;
+        for (y, row) in self.rows.iter().enumerate() {
+            let row_end = row_start.saturating_add(row.as_bytes().len());
+            if offset < row_end.saturating_add(ending_len) {
+                let in_row = offset.saturating_sub(row_start);
+                let x = row
+                    .as_str()
+                    .grapheme_indices(true)
+                    .take_while(|&(index, grapheme)| index.saturating_add(grapheme.len()) <= in_row)
+                    .count();
+                return Some(Position { x, y });
+            }
+            row_start = row_end.saturating_add(ending_len);
+        }
+        (offset == row_start).then_some(Position { x: 0, y: self.rows.len() })
+    }
+
+    /// Returns the offset of the byte at `pos` in the document as it is saved,
+    /// each row followed by the line ending, e.g. to send a range to an
+    /// external tool. It is the inverse of `byte_offset_to_position`, with
+    /// `pos` clamped to the end of its row and to the end of the file.
+    #[must_use]
+    pub fn position_to_byte_offset(&self, pos: &Position) -> usize {
+        let ending_len = self.line_ending.as_bytes().len();
+        let rows_before = self
+            .rows
+            .iter()
+            .take(pos.y)
+            .fold(0, |offset: usize, row| offset.saturating_add(row.as_bytes().len()).saturating_add(ending_len));
+        let in_row = self.rows.get(pos.y).map_or(0, |row| {
+            row.as_str()
+                .grapheme_indices(true)
+                .nth(pos.x)
+                .map_or(row.as_bytes().len(), |(index, _)| index)
+        });
+        rows_before.saturating_add(in_row)
+    }
+
+    /// Returns the index and text of the rows from `before` rows above `at` to
+    /// `after` rows below it, as far as the document goes, e.g. to show a
+    /// search result in context
+    #[must_use]
+    pub fn context(&self, at: &Position, before: usize, after: usize) -> Vec<(usize, String)> {
+        let start = at.y.saturating_sub(before);
+        let end = at.y.saturating_add(after);
+        self.rows
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(end.saturating_sub(start).saturating_add(1))
+            .map(|(y, row)| (y, row.as_str().to_owned()))
+            .collect()
+    }
+
+    /// Returns the line ending written after each row when saving
+    #[must_use]
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    /// Removes the C0 control characters other than tabs, such as `\x00` or
+    /// a bell, from every row as a single undo step, e.g. right after opening
+    /// a downloaded file, and returns how many were removed. Line endings
+    /// are not part of the rows, so they are kept.
+    pub fn strip_control_chars(&mut self) -> usize {
+        let is_control = |grapheme: &str| grapheme.chars().next().is_some_and(|c| c < ' ' && c != '\t');
+        let controls: Vec<Position> = self
+            .rows
+            .iter()
+            .enumerate()
+            .flat_map(|(y, row)| {
+                row.as_str()
+                    .graphemes(true)
+                    .enumerate()
+                    .filter(|&(_, grapheme)| is_control(grapheme))
+                    .map(move |(x, _)| Position { x, y })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        if controls.is_empty() {
+            return 0;
+        }
+        self.as_one_edit(&Position::default(), |document| {
+            for position in controls.iter().rev() {
+                document.delete(position);
+            }
+            Position::default()
+        });
+        controls.len()
+    }
+
+    /// Converts the document to `line_ending`, which the next save writes
+    /// after every row. It can be undone, and the document is dirty while
+    /// the line ending differs from the one of the file on disk.
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        if line_ending == self.line_ending {
+            return;
+        }
+        self.redo_stack.clear();
+        self.as_one_edit(&Position::default(), |document| {
+            document.line_ending = line_ending;
+            Position::default()
+        });
+        self.dirty = self.differs_from_saved();
+        self.saved_hashes.clear();
+    }
+
+    /// Gathers the data shown in the status bar for the given cursor
+    #[must_use]
+    pub fn status_info(&self, cursor: &Position) -> StatusInfo {
+        let column = self.visual_column(cursor);
+        StatusInfo {
+            file_name: self.file_name.clone(),
+            file_type: self.file_type(),
+            is_dirty: self.dirty,
+            lines: self.rows.len(),
+            line: cursor.y.saturating_add(1),
+            column: column.saturating_add(1),
+            line_ending: self.line_ending,
+        }
+    }
+
+    /// Returns a hash of the text of the document, e.g. to tell cheaply if it
+    /// differs from a known state
+    #[must_use]
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.rows.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Returns a boolean indicating if the document has been changed or not
+    #[must_use]
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Returns an option with the elements that corresponds to a certain
+    /// search query passed. With `whole_word`, only matches bounded by
+    /// separators or the edges of the row count.
+    ///
+    /// Searching forward finds the first match starting at or after `at`, and
+    /// searching backward the last match starting strictly before it, see
+    /// `Row::find`.
+    ///
+    /// The search gives up and returns `None` as soon as `cancel` is set,
+    /// e.g. by the editor when the user types a new query. An empty query
+    /// never matches, like in the other searches.
+    #[must_use]
+    pub fn find(&self, query: &str, at: &Position, direction: SearchDirection, whole_word: bool, cancel: Option<&AtomicBool>) -> Option<Position> {
+        if query.is_empty() {
+            return None;
+        }
+        let last = self.rows.len().checked_sub(1)?;
+        if at.y > last && direction == SearchDirection::Forward {
+            return None;
+        }
+        let at = &if at.y > last {
+            Position {
+                x: self.rows.get(last).map_or(0, |row| row.len()),
+                y: last,
+            }
+        } else {
+            at.clone()
+        };
+
+        let mut position = Position { x: at.x, y: at.y };
+
+        let start = if direction == SearchDirection::Forward {
+            at.y
+        } else {
+            0
+        };
+
+        let end = if direction == SearchDirection::Forward {
+            self.rows.len()
+        } else {
+            at.y.saturating_add(1)
+        };
+
+        for _ in start..end {
+            if is_cancelled(cancel) {
+                return None;
+            }
+            if let Some(row) = self.rows.get(position.y) {
+                if let Some(x) = row.find(query, position.x, direction, whole_word.then_some(self.word_chars)) {
+                    position.x = x;
+                    return Some(position);
+                }
+                if direction == SearchDirection::Forward {
+                    position.y = position.y.saturating_add(1);
+                    position.x = 0;
+                } else {
+                    position.y = position.y.saturating_sub(1);
+                    if let Some(r) = self.rows.get(position.y) {
+                        position.x = r.len();
+                    }
+                }
+            } else {
+                return None;
+            }
+        }
+        None
+    }
+
+    /// Same as `find`, but if `wrap` is set and there is no match until the
+    /// start/end of the document, the search goes on from the other end up
+    /// to `at`. The returned boolean tells if the search wrapped around.
+    #[must_use]
+    pub fn find_with_options(&self, query: &str, at: &Position, direction: SearchDirection, wrap: bool, whole_word: bool) -> Option<(Position, bool)> {
+        if let Some(position) = self.find(query, at, direction, whole_word, None) {
+            return Some((position, false));
+        }
+        if !wrap {
+            return None;
+        }
+        let last = self.rows.len().checked_sub(1)?;
+        let (restart, before_at) = if direction == SearchDirection::Forward {
+            (Position::default(), true)
+        } else {
+            let x = self.rows.get(last).map_or(0, |row| row.len());
+            (Position { x, y: last }, false)
+        };
+        self.find(query, &restart, direction, whole_word, None)
+            .filter(|position| position.is_before(at) == before_at)
+            .map(|position| (position, true))
+    }
+
+    /// Same as `find`, but `pattern` can span several rows, with `\n` standing
+    /// for the end of a row, e.g. `"a\nb"` matches at the end of a row `a`
+    /// followed by a row starting with `b`. An empty pattern never matches.
+    ///
+    /// Returns the start of the match and its length in graphemes, each row
+    /// break counting as one like in `delete`, so that the caller can select
+    /// or replace exactly the match.
+    #[must_use]
+    pub fn find_multiline(&self, pattern: &str, at: &Position, direction: SearchDirection) -> Option<(Position, usize)> {
+        if pattern.is_empty() {
+            return None;
+        }
+        let len = pattern.graphemes(true).count();
+        let ending = self.line_ending.as_str();
+        let pattern = pattern.replace('\n', ending);
+        let text: String = self.rows.iter().flat_map(|row| [row.as_str(), ending]).collect();
+        let start = self.position_to_byte_offset(at);
+        let offset = if direction == SearchDirection::Forward {
+            text.get(start..)?.find(&pattern)?.saturating_add(start)
+        } else {
+            text.char_indices()
+                .map(|(index, _)| index)
+                .take_while(|index| *index < start)
+                .filter(|index| text.get(*index..).is_some_and(|rest| rest.starts_with(&pattern)))
+                .last()?
+        };
+        Some((self.byte_offset_to_position(offset)?, len))
+    }
+
+    /// Returns the start of every non-overlapping match of `query` in the
+    /// document, in order, or none for an empty query. If `cancel` gets set,
+    /// it stops and returns the matches found so far.
+    #[must_use]
+    pub fn find_all(&self, query: &str, whole_word: bool, cancel: Option<&AtomicBool>) -> Vec<Position> {
+        self.find_all_with(query, whole_word, cancel, |_| {})
+    }
+
+    /// Same as `find_all`, calling `on_row` with the index of each row
+    /// before searching it
+    fn find_all_with<F: FnMut(usize)>(&self, query: &str, whole_word: bool, cancel: Option<&AtomicBool>, mut on_row: F) -> Vec<Position> {
+        let query_len = query.graphemes(true).count();
+        let mut positions = Vec::new();
+        if query_len == 0 {
+            return positions;
+        }
+        for (y, row) in self.rows.iter().enumerate() {
+            on_row(y);
+            if is_cancelled(cancel) {
+                break;
+            }
+            let mut x = 0;
+            while let Some(found) = row.find(query, x, SearchDirection::Forward, whole_word.then_some(self.word_chars)) {
+                positions.push(Position { x: found, y });
+                x = found.saturating_add(query_len);
+            }
+        }
+        positions
+    }
+
+    /// Returns the number of non-overlapping matches of `query` in the
+    /// document, e.g. to show it next to the search prompt
+    #[must_use]
+    pub fn count_matches(&self, query: &str, whole_word: bool) -> usize {
+        self.find_all(query, whole_word, None).len()
+    }
+
+    /// Replaces every non-overlapping match of `query` with `replacement`
+    /// and returns how many were replaced. Matches are all found before
+    /// replacing, so a replacement that contains `query` is not replaced
+    /// again.
+    pub fn replace_all(&mut self, query: &str, replacement: &str, whole_word: bool) -> usize {
+        let query_len = query.graphemes(true).count();
+        let positions = self.find_all(query, whole_word, None);
+        for start in positions.iter().rev() {
+            let end = Position {
+                x: start.x.saturating_add(query_len),
+                y: start.y,
+            };
+            self.replace_range(start, &end, replacement);
+        }
+        positions.len()
+    }
+}
+
+/// Saves every document in `docs`, going on after a failure, and returns
+/// the result of each save in the same order, so that no document is left
+/// unsaved without the caller knowing. A document without a file name
+/// fails with `ErrorKind::NotFound`, since it can not be saved.
+pub fn save_all(docs: &mut [Document]) -> Vec<Result<(), Error>> {
+    docs.iter_mut()
+        .map(|document| {
+            if document.file_name.is_none() {
+                return Err(Error::new(ErrorKind::NotFound, "No file name"));
+            }
+            document.save().map(|_| ())
+        })
+        .collect()
+}
+
+fn is_cancelled(cancel: Option<&AtomicBool>) -> bool {
+    cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+}
+
+/// Returns the number of bytes a row takes on disk, including its line ending.
+fn row_len(row: &Row, line_ending: LineEnding) -> u64 {
+    let len = row.as_bytes().len().saturating_add(line_ending.as_bytes().len());
+    len.try_into().expect("Failed converting row length to u64")
+}
+
+/// Returns the graphemes of `row`, each with whether it is code rather than
+/// part of a string or a comment, as of the last call to `highlight`
+fn code_graphemes(row: &Row) -> Vec<(&str, bool)> {
+    let mut char_index: usize = 0;
+    row.as_str()
+        .graphemes(true)
+        .map(|grapheme| {
+            let hl_type = row.highlighting().get(char_index);
+            char_index = char_index.saturating_add(grapheme.chars().count());
+            let is_code = !hl_type.is_some_and(|hl_type| {
+                matches!(
+                    hl_type,
+                    highlighting::Type::String
+                        | highlighting::Type::Character
+                        | highlighting::Type::Comment
+                        | highlighting::Type::MultilineComment
+                )
+            });
+            (grapheme, is_code)
+        })
+        .collect()
+}
+
+/// Removes the CSI sequences, such as the `\x1b[31m` color codes, the OSC
+/// sequences, such as window titles, and the other two-character escapes
+/// from `contents`
+fn strip_ansi(contents: &str) -> String {
+    let mut result = String::with_capacity(contents.len());
+    let mut chars = contents.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('[') => {
+                while chars.next_if(|next| ('\x20'..='\x3f').contains(next)).is_some() {}
+                chars.next_if(|next| ('\x40'..='\x7e').contains(next));
+            }
+            Some(']') => {
+                while let Some(next) = chars.next() {
+                    if next == '\x07' || (next == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                        break;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    result
+}
+
+/// Expands a leading `~` and the environment variables in `path`, looking
+/// their values up with `var`, see `Document::open_expanded`
+fn expand_path(path: &str, var: impl Fn(&str) -> Option<String>) -> String {
+    let path = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => match var("HOME") {
+            Some(home) => format!("{home}{rest}"),
+            None => path.to_owned(),
+        },
+        _ => path.to_owned(),
+    };
+    let mut result = String::new();
+    let mut chars = path.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            result.push(c);
+            continue;
+        }
+        let braced = chars.next_if_eq(&'{').is_some();
+        let mut name = String::new();
+        while let Some(next) = chars.next_if(|next| next.is_ascii_alphanumeric() || *next == '_') {
+            name.push(next);
+        }
+        let closed = !braced || chars.next_if_eq(&'}').is_some();
+        match var(&name) {
+            Some(value) if closed && !name.is_empty() => result.push_str(&value),
+            _ => {
+                result.push('$');
+                if braced {
+                    result.push('{');
+                }
+                result.push_str(&name);
+                if braced && closed {
+                    result.push('}');
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Splits a `path[:line[:column]]` spec into its parts
+fn split_position_spec(spec: &str) -> (&str, Option<usize>, Option<usize>) {
+    let number = |part: &str| -> Option<usize> {
+        if part.is_empty() || !part.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        part.parse().ok()
+    };
+    let Some((rest, last)) = spec.rsplit_once(':') else {
+        return (spec, None, None);
+    };
+    let Some(last) = number(last) else {
+        return (spec, None, None);
+    };
+    match rest.rsplit_once(':') {
+        Some((path, line)) if number(line).is_some() => (path, number(line), Some(last)),
+        _ => (rest, Some(last), None),
+    }
+}
+
+fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+    use std::io::Cursor;
+
+    struct CountingWriter {
+        inner: Cursor<Vec<u8>>,
+        written: usize,
+    }
+
+    impl Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let written = self.inner.write(buf)?;
+            self.written = self.written.saturating_add(written);
+            Ok(written)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    impl Read for CountingWriter {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+
+    impl Seek for CountingWriter {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    fn document(lines: usize) -> Document {
+        let mut document = Document::default();
+        for index in 0..lines {
+            document.rows.push(Rc::new(Row::from(format!("line number {index}").as_str())));
+        }
+        document.count_line_widths();
+        document.take_snapshot();
+        document
+    }
+
+    #[test]
+    fn save_incremental_writes_only_changed_rows() {
+        let mut document = document(1000);
+        let mut on_disk = Vec::new();
+        document.write_rows(&mut on_disk, 0).expect("Failed writing rows");
+        let last = Position { x: 0, y: 999 };
+        document.insert(&last, 'x');
+
+        let mut full = CountingWriter { inner: Cursor::new(Vec::new()), written: 0 };
+        document.write_rows(&mut full, 0).expect("Failed writing rows");
+        let mut incremental = CountingWriter { inner: Cursor::new(on_disk), written: 0 };
+        let end = document.write_incremental(&mut incremental).expect("Failed writing rows");
+
+        #[allow(clippy::integer_division_remainder_used)]
+        let limit = full.written / 100;
+        assert!(incremental.written < limit);
+        let mut result = incremental.inner.into_inner();
+        result.truncate(end.expect("Expected an incremental save").try_into().expect("Failed converting end"));
+        assert_eq!(result, full.inner.into_inner());
+    }
+
+    #[test]
+    fn save_incremental_falls_back_when_prefix_diverges() {
+        let mut document = document(3);
+        let mut on_disk = Vec::new();
+        document.write_rows(&mut on_disk, 0).expect("Failed writing rows");
+        if let Some(first) = on_disk.first_mut() {
+            *first = b'L';
+        }
+        document.insert(&Position { x: 0, y: 2 }, 'x');
+
+        let mut file = Cursor::new(on_disk);
+        assert_eq!(document.write_incremental(&mut file).expect("Failed writing rows"), None);
+    }
+
+    #[test]
+    fn yank_pop_pastes_earlier_kill() {
+        let mut document = Document::default();
+        let start = Position::default();
+        document.insert_str(&start, "alpha beta gamma");
+        assert_eq!(document.delete_word(&start), "alpha");
+        assert_eq!(document.delete_word(&start), " beta");
+
+        document.yank(&start);
+        assert_eq!(document.row(0).map(Row::as_str), Some(" beta gamma"));
+        document.yank_pop();
+        assert_eq!(document.row(0).map(Row::as_str), Some("alpha gamma"));
+    }
+
+    #[test]
+    fn yank_pop_reindents_on_paste() {
+        let mut document = Document::default();
+        document.set_reindent_on_paste(true);
+        document.insert_str(&Position::default(), "fn f() {\n    ");
+        document.kill_ring = VecDeque::from(["a".to_owned(), "  b\n  c".to_owned()]);
+        document.yank(&Position { x: 4, y: 1 });
+        assert_eq!(document.row(1).map(Row::as_str), Some("    a"));
+        assert_eq!(document.yank_pop(), Some(Position { x: 5, y: 2 }));
+        assert_eq!(document.row(1).map(Row::as_str), Some("    b"));
+        assert_eq!(document.row(2).map(Row::as_str), Some("    c"));
+    }
+
+    #[test]
+    fn insert_block_reindented_adopts_row_indentation() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "fn main() {\n        ");
+        let end = document.insert_block_reindented(&Position { x: 8, y: 1 }, "    if x {\n        y();\n\n    }");
+        assert_eq!(end, Position { x: 9, y: 4 });
+        assert_eq!(document.row(1).map(Row::as_str), Some("        if x {"));
+        assert_eq!(document.row(2).map(Row::as_str), Some("            y();"));
+        assert_eq!(document.row(3).map(Row::as_str), Some(""));
+        assert_eq!(document.row(4).map(Row::as_str), Some("        }"));
+    }
+
+    #[test]
+    fn expand_to_word_boundaries_snaps_to_words() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "alpha beta, gamma\ndelta");
+        let expand = |start: Position, end: Position| document.expand_to_word_boundaries(&start, &end);
+        assert_eq!(
+            expand(Position { x: 2, y: 0 }, Position { x: 8, y: 0 }),
+            (Position { x: 0, y: 0 }, Position { x: 10, y: 0 })
+        );
+        assert_eq!(
+            expand(Position { x: 5, y: 0 }, Position { x: 11, y: 0 }),
+            (Position { x: 5, y: 0 }, Position { x: 11, y: 0 })
+        );
+        assert_eq!(
+            expand(Position { x: 14, y: 0 }, Position { x: 2, y: 1 }),
+            (Position { x: 12, y: 0 }, Position { x: 5, y: 1 })
+        );
+    }
+
+    #[test]
+    fn delete_word_follows_word_chars() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "foo-bar baz\nfoo-bar baz");
+        assert_eq!(document.delete_word(&Position::default()), "foo");
+        document.set_word_chars(WordCharSet::IncludingDash);
+        assert_eq!(document.delete_word(&Position { x: 0, y: 1 }), "foo-bar");
+        assert_eq!(document.row(0).map(Row::as_str), Some("-bar baz"));
+        assert_eq!(document.row(1).map(Row::as_str), Some(" baz"));
+    }
+
+    #[test]
+    fn highlight_breakdown_counts_comments() {
+        let mut document = Document {
+            file_type: FileType::from("main.rs"),
+            ..Document::default()
+        };
+        document.insert_str(&Position::default(), "let x = 1; // note");
+        document.highlight(&None, None);
+        let breakdown = document.highlight_breakdown();
+        assert_eq!(breakdown.get(&highlighting::Type::Comment), Some(&7));
+        assert_eq!(breakdown.get(&highlighting::Type::Number), Some(&1));
+    }
+
+    #[test]
+    fn highlight_leaves_clean_rows_shared() {
+        let mut document = Document {
+            file_type: FileType::from("data.json"),
+            ..Document::default()
+        };
+        document.insert_str(&Position::default(), "{\n  \"a\": [1],\n  \"b\": 2\n}");
+        document.highlight(&None, None);
+        let shared = document.clone();
+        document.highlight(&None, None);
+        assert!(document.rows.iter().zip(&shared.rows).all(|(row, other)| Rc::ptr_eq(row, other)));
+
+        document.insert_str(&Position::default(), "[");
+        document.highlight(&None, None);
+        assert_eq!(document.row(1).and_then(|row| row.highlighting().get(7)), Some(&highlighting::Type::Bracket(2)));
+        assert_eq!(document.row(3).and_then(|row| row.highlighting().first()), Some(&highlighting::Type::Bracket(1)));
+    }
+
+    #[test]
+    fn rehighlight_all_follows_file_type_change() {
+        let mut document = Document::from_reader(Cursor::new(&b"/* a\nb */ fn main() {}\n"[..])).expect("Failed reading document");
+        document.highlight(&None, None);
+        assert_eq!(document.row(1).and_then(|row| row.highlighting().get(5)), Some(&highlighting::Type::None));
+        document.file_type = FileType::from("main.rs");
+        document.rehighlight_all();
+        assert_eq!(document.row(1).and_then(|row| row.highlighting().get(2)), Some(&highlighting::Type::MultilineComment));
+        assert_eq!(document.row(1).and_then(|row| row.highlighting().get(5)), Some(&highlighting::Type::PrimaryKeywords));
+    }
+
+    #[test]
+    fn set_file_type_highlights_without_dirtying() {
+        let mut document = Document::from_str_stripping_ansi("let x = 1;\n");
+        document.highlight(&None, None);
+        assert_eq!(document.row(0).and_then(|row| row.highlighting().first()), Some(&highlighting::Type::None));
+        document.set_file_type(FileType::from("main.rs"));
+        assert_eq!(document.file_type(), "Rust");
+        assert_eq!(document.row(0).and_then(|row| row.highlighting().first()), Some(&highlighting::Type::PrimaryKeywords));
+        assert!(!document.is_dirty());
+    }
+
+    #[test]
+    fn unbalanced_brackets_skips_strings_and_comments() {
+        let mut document = Document {
+            file_type: FileType::from("main.rs"),
+            ..Document::default()
+        };
+        document.insert_str(&Position::default(), "fn f() { (\nlet s = \"}\"; // )\n]");
+        document.highlight(&None, None);
+        assert_eq!(
+            document.unbalanced_brackets(),
+            vec![Position { x: 7, y: 0 }, Position { x: 9, y: 0 }, Position { x: 0, y: 2 }]
+        );
+        document.insert_str(&Position { x: 0, y: 2 }, ")}");
+        document.delete(&Position { x: 2, y: 2 });
+        document.highlight(&None, None);
+        assert_eq!(document.unbalanced_brackets(), Vec::<Position>::new());
+    }
+
+    #[test]
+    fn update_word_highlight_only_when_word_changes() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "alpha beta alpha");
+        assert!(document.update_word_highlight(&Position { x: 1, y: 0 }));
+        assert_eq!(document.row(0).and_then(|row| row.highlighting().get(12)), Some(&highlighting::Type::Match));
+        assert!(!document.update_word_highlight(&Position { x: 3, y: 0 }));
+        assert!(!document.update_word_highlight(&Position { x: 13, y: 0 }));
+        assert!(document.update_word_highlight(&Position { x: 7, y: 0 }));
+        assert_eq!(document.row(0).and_then(|row| row.highlighting().get(12)), Some(&highlighting::Type::None));
+        assert!(document.update_word_highlight(&Position { x: 5, y: 0 }));
+        assert_eq!(document.row(0).and_then(|row| row.highlighting().get(7)), Some(&highlighting::Type::None));
+        assert!(!document.update_word_highlight(&Position { x: 0, y: 4 }));
+
+        assert!(document.update_word_highlight(&Position { x: 1, y: 0 }));
+        document.insert(&Position { x: 16, y: 0 }, '!');
+        document.highlight(&None, None);
+        assert_eq!(document.row(0).and_then(|row| row.highlighting().get(12)), Some(&highlighting::Type::None));
+        assert!(document.update_word_highlight(&Position { x: 1, y: 0 }));
+        assert_eq!(document.row(0).and_then(|row| row.highlighting().get(12)), Some(&highlighting::Type::Match));
+    }
+
+    #[test]
+    fn typing_inside_a_word_redoes_the_word_highlight() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "alpha alpa beta");
+        assert!(document.update_word_highlight(&Position { x: 1, y: 0 }));
+        document.insert(&Position { x: 9, y: 0 }, 'h');
+        assert!(document.update_word_highlight(&Position { x: 1, y: 0 }));
+        assert_eq!(document.row(0).and_then(|row| row.highlighting().get(6)), Some(&highlighting::Type::Match));
+    }
+
+    #[test]
+    fn set_annotations_replaces_the_default_words() {
+        let mut document = Document {
+            file_type: FileType::from("script.py"),
+            ..Document::default()
+        };
+        document.insert_str(&Position::default(), "x = 1  # TODO: NOTE");
+        document.highlight(&None, None);
+        assert_eq!(document.annotations(), vec![(Position { x: 9, y: 0 }, "x = 1  # TODO: NOTE".to_owned())]);
+        document.set_annotations(&["NOTE"]);
+        assert_eq!(document.annotations(), vec![(Position { x: 15, y: 0 }, "x = 1  # TODO: NOTE".to_owned())]);
+    }
+
+    #[test]
+    fn annotations_lists_comment_rows() {
+        let mut document = Document {
+            file_type: FileType::from("main.rs"),
+            ..Document::default()
+        };
+        document.insert_str(
+            &Position::default(),
+            "// TODO: split this\nfn main() {\n    let todo = \"TODO\";\n    run(); // FIXME later\n}",
+        );
+        document.highlight(&None, None);
+        assert_eq!(
+            document.annotations(),
+            vec![
+                (Position { x: 3, y: 0 }, "// TODO: split this".to_owned()),
+                (Position { x: 14, y: 3 }, "run(); // FIXME later".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn swap_rows_exchanges_contents() {
+        let mut document = document(5);
+        document.swap_rows(5, 0);
+        assert!(!document.is_dirty());
+        document.swap_rows(0, 4);
+        assert_eq!(document.row(0).map(Row::as_str), Some("line number 4"));
+        assert_eq!(document.row(4).map(Row::as_str), Some("line number 0"));
+        assert_eq!(document.row(2).map(Row::as_str), Some("line number 2"));
+        assert!(document.is_dirty());
+        assert_eq!(document.undo(), Some(Position { x: 0, y: 0 }));
+        assert_eq!(document.row(0).map(Row::as_str), Some("line number 0"));
+        assert_eq!(document.row(4).map(Row::as_str), Some("line number 4"));
+    }
+
+    #[test]
+    fn visual_line_count_sums_wrapped_segments() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "short\nabcdefghijkl\n\tab\tcdefghijklmnopq");
+        assert_eq!(document.wrap_row(1, 10), vec![(0, 10), (10, 12)]);
+        assert_eq!(document.wrap_row(2, 10), vec![(0, 6), (6, 16), (16, 19)]);
+        assert_eq!(document.wrap_row(3, 10), Vec::new());
+        assert_eq!(document.visual_line_count(10), 6);
+        assert_eq!(document.visual_line_count(100), 3);
+    }
+
+    #[test]
+    fn last_edit_position_follows_later_edits() {
+        let mut document = document(12);
+        document.insert(&Position { x: 4, y: 5 }, 'x');
+        document.insert(&Position { x: 5, y: 5 }, 'y');
+        document.delete(&Position { x: 2, y: 10 });
+        assert_eq!(document.last_edit_position(0), Some(Position { x: 2, y: 10 }));
+        assert_eq!(document.last_edit_position(1), Some(Position { x: 5, y: 5 }));
+        assert_eq!(document.last_edit_position(2), None);
+
+        document.insert(&Position { x: 0, y: 1 }, '\n');
+        assert_eq!(document.last_edit_position(1), Some(Position { x: 2, y: 11 }));
+        assert_eq!(document.last_edit_position(2), Some(Position { x: 5, y: 6 }));
+        document.delete(&Position { x: 13, y: 5 });
+        assert_eq!(document.last_edit_position(0), Some(Position { x: 13, y: 5 }));
+        assert_eq!(document.last_edit_position(2), Some(Position { x: 2, y: 10 }));
+        assert_eq!(document.last_edit_position(3), Some(Position { x: 18, y: 5 }));
+    }
+
+    #[test]
+    fn bracket_match_highlight_marks_both_brackets() {
+        let mut document = Document {
+            file_type: FileType::from("main.rs"),
+            ..Document::default()
+        };
+        document.insert_str(&Position::default(), "fn f(a: [u8; 2]) {\n    g(\")\", (1));\n}");
+        document.highlight(&None, None);
+        assert_eq!(document.matching_bracket(&Position { x: 4, y: 0 }), Some(Position { x: 15, y: 0 }));
+        assert_eq!(document.matching_bracket(&Position { x: 15, y: 0 }), Some(Position { x: 4, y: 0 }));
+        assert_eq!(document.matching_bracket(&Position { x: 17, y: 0 }), Some(Position { x: 0, y: 2 }));
+        assert_eq!(document.matching_bracket(&Position { x: 0, y: 2 }), Some(Position { x: 17, y: 0 }));
+        assert_eq!(document.matching_bracket(&Position { x: 5, y: 1 }), Some(Position { x: 14, y: 1 }));
+        assert_eq!(document.matching_bracket(&Position { x: 7, y: 1 }), None);
+        assert_eq!(document.matching_bracket(&Position { x: 0, y: 0 }), None);
+
+        document.bracket_match_highlight(&Position { x: 4, y: 0 });
+        let row = document.row(0).expect("Failed getting row");
+        assert_eq!(row.highlight_at(4), Some(highlighting::Type::MatchingBracket));
+        assert_eq!(row.highlight_at(15), Some(highlighting::Type::MatchingBracket));
+        document.clear_bracket_highlight();
+        let cleared = document.row(0).expect("Failed getting row");
+        assert_eq!(cleared.highlight_at(4), Some(highlighting::Type::None));
+        assert_eq!(cleared.highlight_at(15), Some(highlighting::Type::None));
+    }
+
+    #[test]
+    fn json_brackets_colored_by_depth() {
+        let mut document = Document {
+            file_type: FileType::from("data.json"),
+            ..Document::default()
+        };
+        document.insert_str(&Position::default(), "{\"a\": [1, {\"b\": null}],\n\"c\": \"{\"}");
+        document.highlight(&None, None);
+        let hl_type = |x: usize, y: usize| document.row(y)?.highlighting().get(x).copied();
+        assert_eq!(hl_type(0, 0), Some(highlighting::Type::Bracket(0)));
+        assert_eq!(hl_type(6, 0), Some(highlighting::Type::Bracket(1)));
+        assert_eq!(hl_type(10, 0), Some(highlighting::Type::Bracket(2)));
+        assert_eq!(hl_type(20, 0), Some(highlighting::Type::Bracket(2)));
+        assert_eq!(hl_type(21, 0), Some(highlighting::Type::Bracket(1)));
+        assert_eq!(hl_type(4, 0), Some(highlighting::Type::Punctuation));
+        assert_eq!(hl_type(16, 0), Some(highlighting::Type::PrimaryKeywords));
+        assert_eq!(hl_type(6, 1), Some(highlighting::Type::String));
+        assert_eq!(hl_type(8, 1), Some(highlighting::Type::Bracket(0)));
+    }
+
+    #[test]
+    fn leading_comment_block_above_function() {
+        let mut document = Document {
+            file_type: FileType::from("main.rs"),
+            ..Document::default()
+        };
+        document.insert_str(&Position::default(), "let x = 1; // not this\n    /// Adds one\n    ///\n    /// to x\n    fn add() {}");
+        assert_eq!(document.leading_comment_block(4), None);
+        document.highlight(&None, None);
+        assert_eq!(
+            document.leading_comment_block(4),
+            Some((1, 3, "/// Adds one\n///\n/// to x".to_owned()))
+        );
+        assert_eq!(document.leading_comment_block(1), None);
+        assert_eq!(document.leading_comment_block(0), None);
+    }
+
+    #[test]
+    fn markdown_fence_suppresses_headings() {
+        let mut document = Document {
+            file_type: FileType::from("README.md"),
+            ..Document::default()
+        };
+        document.insert_str(&Position::default(), "# Title\n```\n#not a heading\n```\nsome *em* and `code`\n## After");
+        document.highlight(&None, None);
+        let hl_type = |x: usize, y: usize| document.row(y)?.highlighting().get(x).copied();
+        assert_eq!(hl_type(0, 0), Some(highlighting::Type::Heading));
+        assert_eq!(hl_type(0, 2), Some(highlighting::Type::Code));
+        assert_eq!(hl_type(0, 3), Some(highlighting::Type::Code));
+        assert_eq!(hl_type(4, 4), Some(highlighting::Type::None));
+        assert_eq!(hl_type(5, 4), Some(highlighting::Type::Emphasis));
+        assert_eq!(hl_type(8, 4), Some(highlighting::Type::Emphasis));
+        assert_eq!(hl_type(9, 4), Some(highlighting::Type::None));
+        assert_eq!(hl_type(14, 4), Some(highlighting::Type::Code));
+        assert_eq!(hl_type(0, 5), Some(highlighting::Type::Heading));
+    }
+
+    #[test]
+    fn from_str_stripping_ansi_keeps_text() {
+        let document = Document::from_str_stripping_ansi("\x1b[31mred\x1b[0m\n\x1b[1;38;5;82mbold [green]\x1b[m\x1b]0;title\x07!");
+        assert_eq!(document.row(0).map(Row::as_str), Some("red"));
+        assert_eq!(document.row(1).map(Row::as_str), Some("bold [green]!"));
+    }
+
+    #[test]
+    fn extract_rows_copies_a_range() {
+        let mut document = document(4);
+        document.file_type = FileType::from("main.rs");
+        let extracted = document.extract_rows(1, 2);
+        assert_eq!(extracted.len(), 2);
+        assert_eq!(extracted.row(0).map(Row::as_str), Some("line number 1"));
+        assert_eq!(extracted.row(1).map(Row::as_str), Some("line number 2"));
+        assert_eq!(extracted.file_type(), "Rust");
+        assert_eq!(extracted.file_name, None);
+        assert!(extracted.is_dirty());
+        assert_eq!(extracted.max_line_width(), 13);
+        assert_eq!(document.extract_rows(3, 10).len(), 1);
+        assert!(document.extract_rows(5, 10).is_empty());
+        assert!(document.extract_rows(2, 1).is_empty());
+    }
+
+    #[test]
+    fn from_reader_splits_rows() {
+        let document = Document::from_reader(Cursor::new(&b"first\nsecond\n"[..])).expect("Failed reading document");
+        assert_eq!(document.len(), 2);
+        assert_eq!(document.row(1).map(Row::as_str), Some("second"));
+        assert!(document.file_name.is_none());
+        assert_eq!(document.file_type(), "Plain Text");
+    }
+
+    #[test]
+    fn open_lossy_replaces_invalid_utf8() {
+        let path = std::env::temp_dir().join("hammare_open_lossy.txt");
+        fs::write(&path, b"caf\xe9\n").expect("Failed writing file");
+        let file_name = path.to_str().expect("Failed converting path");
+        assert!(Document::open(file_name).is_err());
+        let document = Document::open_lossy(file_name).expect("Failed opening file");
+        assert_eq!(document.row(0).map(Row::as_str), Some("caf\u{FFFD}"));
+        assert!(document.is_lossy());
+        let (lossy, position) = Document::open_lossy_at(&format!("{file_name}:1:3")).expect("Failed opening file");
+        assert_eq!(lossy.file_name.as_deref(), Some(file_name));
+        assert_eq!(position, Position { x: 2, y: 0 });
+        fs::remove_file(&path).expect("Failed removing file");
+    }
+
+    #[test]
+    fn counts_leading_and_trailing_blank_lines() {
+        let mut document = Document::default();
+        assert_eq!((document.leading_blank_lines(), document.trailing_blank_lines()), (0, 0));
+        document.insert_str(&Position::default(), "\n \ntext\n\nmore\n\t\n\n");
+        assert_eq!(document.leading_blank_lines(), 2);
+        assert_eq!(document.trailing_blank_lines(), 3);
+    }
+
+    #[test]
+    fn paragraph_bounds_stop_at_blank_lines() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "first\n\nsecond\nthird\nfourth\n  \n\nlast");
+        let (start, end) = document.paragraph_bounds(&Position { x: 2, y: 3 });
+        assert_eq!(start, Position { x: 0, y: 2 });
+        assert_eq!(end, Position { x: 6, y: 4 });
+    }
+
+    #[test]
+    fn reflow_paragraph_wraps_to_width() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "  the quick brown fox jumps over the lazy dog again\nand again\n\nnext");
+        document.reflow_paragraph(&Position { x: 0, y: 1 }, 20);
+        let rows: Vec<&str> = (0..document.len()).filter_map(|y| document.row(y).map(Row::as_str)).collect();
+        assert_eq!(
+            rows,
+            vec!["  the quick brown", "  fox jumps over the", "  lazy dog again and", "  again", "", "next"]
+        );
+        assert!(rows.iter().all(|row| row.len() <= 20));
+        assert_eq!(document.undo(), Some(Position::default()));
+        assert_eq!(document.row(0).map(Row::as_str), Some("  the quick brown fox jumps over the lazy dog again"));
+        assert_eq!(document.row(1).map(Row::as_str), Some("and again"));
+
+        let mut wide = Document::default();
+        wide.insert_str(&Position::default(), "\u{65e5}\u{672c}\u{8a9e} \u{65e5}\u{672c}\u{8a9e} ab");
+        wide.reflow_paragraph(&Position::default(), 10);
+        assert_eq!(wide.row(0).map(Row::as_str), Some("\u{65e5}\u{672c}\u{8a9e}"));
+        assert_eq!(wide.row(1).map(Row::as_str), Some("\u{65e5}\u{672c}\u{8a9e} ab"));
+    }
+
+    #[test]
+    fn next_paragraph_skips_blank_runs() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "first\n\nsecond\nthird\n  \n\nlast");
+        let down = |y| document.next_paragraph(&Position { x: 0, y }, VerticalDirection::Down);
+        assert_eq!(down(0), Position { x: 0, y: 1 });
+        assert_eq!(down(1), Position { x: 0, y: 4 });
+        assert_eq!(down(4), Position { x: 4, y: 6 });
+        let up = |y| document.next_paragraph(&Position { x: 0, y }, VerticalDirection::Up);
+        assert_eq!(up(6), Position { x: 0, y: 5 });
+        assert_eq!(up(5), Position { x: 0, y: 1 });
+        assert_eq!(up(1), Position { x: 0, y: 0 });
+    }
+
+    #[test]
+    fn split_line_returns_start_of_new_row() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "hello\n    world");
+        assert_eq!(document.split_line(&Position { x: 2, y: 0 }), Position { x: 0, y: 1 });
+        assert_eq!(document.row(0).map(Row::as_str), Some("he"));
+        assert_eq!(document.row(1).map(Row::as_str), Some("llo"));
+
+        document.set_auto_indent(true);
+        assert_eq!(document.split_line(&Position { x: 6, y: 2 }), Position { x: 4, y: 3 });
+        assert_eq!(document.row(2).map(Row::as_str), Some("    wo"));
+        assert_eq!(document.row(3).map(Row::as_str), Some("    rld"));
+    }
+
+    #[test]
+    fn split_line_continues_line_comments() {
+        let mut document = Document {
+            file_type: FileType::from("main.rs"),
+            ..Document::default()
+        };
+        document.insert_str(&Position::default(), "    // first");
+        let next = document.split_line(&Position { x: 12, y: 0 });
+        assert_eq!(next, Position { x: 7, y: 1 });
+        assert_eq!(document.row(1).map(Row::as_str), Some("    // "));
+        let next = document.split_line(&next);
+        assert_eq!(next, Position { x: 0, y: 2 });
+        assert_eq!(document.row(2).map(Row::as_str), Some(""));
+
+        document.insert_str(&Position { x: 0, y: 2 }, "/// doc");
+        assert_eq!(document.split_line(&Position { x: 7, y: 2 }), Position { x: 4, y: 3 });
+        assert_eq!(document.row(3).map(Row::as_str), Some("/// "));
+        document.insert_str(&Position { x: 0, y: 4 }, "let x = 1; // not a line comment");
+        assert_eq!(document.split_line(&Position { x: 32, y: 4 }), Position { x: 0, y: 5 });
+        assert_eq!(document.split_line(&Position { x: 2, y: 0 }), Position { x: 0, y: 1 });
+    }
+
+    #[test]
+    fn backspace_joins_with_previous_row() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "one\ntwo");
+        assert_eq!(document.backspace(&Position { x: 0, y: 1 }), Position { x: 3, y: 0 });
+        assert_eq!(document.row(0).map(Row::as_str), Some("onetwo"));
+        assert_eq!(document.len(), 1);
+        assert_eq!(document.backspace(&Position { x: 3, y: 0 }), Position { x: 2, y: 0 });
+        assert_eq!(document.row(0).map(Row::as_str), Some("ontwo"));
+        assert_eq!(document.backspace(&Position::default()), Position::default());
+        assert_eq!(document.backspace(&Position { x: 0, y: 1 }), Position { x: 5, y: 0 });
+        assert_eq!(document.row(0).map(Row::as_str), Some("ontwo"));
+    }
+
+    #[test]
+    fn backspace_removes_indentation_to_tab_stop() {
+        let mut document = Document::default();
+        document.set_tab_width(4);
+        document.insert_str(&Position::default(), "        code\n      x = 1\n\t      y");
+        assert_eq!(document.backspace(&Position { x: 8, y: 0 }), Position { x: 4, y: 0 });
+        assert_eq!(document.row(0).map(Row::as_str), Some("    code"));
+        assert_eq!(document.backspace(&Position { x: 6, y: 1 }), Position { x: 4, y: 1 });
+        assert_eq!(document.row(1).map(Row::as_str), Some("    x = 1"));
+        assert_eq!(document.backspace(&Position { x: 6, y: 1 }), Position { x: 5, y: 1 });
+        assert_eq!(document.row(1).map(Row::as_str), Some("    x= 1"));
+        assert_eq!(document.backspace(&Position { x: 7, y: 2 }), Position { x: 5, y: 2 });
+        assert_eq!(document.row(2).map(Row::as_str), Some("\t    y"));
+    }
+
+    #[test]
+    fn out_of_range_edits_do_not_panic() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "one\ntwo");
+        document.delete(&Position { x: 100, y: 100 });
+        document.delete(&Position { x: 100, y: 0 });
+        document.delete(&Position { x: 3, y: 1 });
+        document.insert(&Position { x: 0, y: 100 }, 'x');
+        document.insert(&Position { x: 100, y: 1 }, '!');
+        assert_eq!(document.len(), 2);
+        assert_eq!(document.row(0).map(Row::as_str), Some("one"));
+        assert_eq!(document.row(1).map(Row::as_str), Some("two!"));
+    }
+
+    #[test]
+    fn row_mut_marks_document_dirty() {
+        let mut document = document(3);
+        document.highlight(&None, None);
+        assert!(!document.is_dirty());
+        assert!(document.row_mut(3).is_none());
+        assert!(!document.is_dirty());
+        if let Some(row) = document.row_mut(1) {
+            row.insert(0, '>');
+        }
+        document.rehighlight_from(1);
+        assert!(document.is_dirty());
+        assert_eq!(document.row(1).map(Row::as_str), Some(">line number 1"));
+        assert_eq!(document.max_line_width(), 14);
+        assert_eq!(document.undo(), Some(Position { x: 0, y: 1 }));
+        assert_eq!(document.row(1).map(Row::as_str), Some("line number 1"));
+    }
+
+    #[test]
+    fn edits_starting_from_an_empty_document() {
+        let mut document = Document::default();
+        assert!(document.is_empty());
+        document.delete(&Position::default());
+        assert_eq!(document.backspace(&Position::default()), Position::default());
+        assert!(document.is_empty());
+        assert!(!document.is_dirty());
+
+        document.insert(&Position::default(), 'a');
+        assert_eq!(document.len(), 1);
+        assert_eq!(document.row(0).map(Row::as_str), Some("a"));
+        document.delete(&Position::default());
+        assert_eq!(document.len(), 1);
+        assert_eq!(document.row(0).map(Row::as_str), Some(""));
+
+        let mut blank_lines = Document::default();
+        assert_eq!(blank_lines.split_line(&Position::default()), Position { x: 0, y: 1 });
+        assert_eq!(blank_lines.len(), 1);
+        assert_eq!(blank_lines.row(0).map(Row::as_str), Some(""));
+        blank_lines.insert(&Position { x: 0, y: 1 }, '\n');
+        assert_eq!(blank_lines.len(), 2);
+        blank_lines.insert(&Position { x: 0, y: 2 }, 'b');
+        assert_eq!(blank_lines.len(), 3);
+        assert_eq!(blank_lines.backspace(&Position { x: 0, y: 2 }), Position { x: 0, y: 1 });
+        assert_eq!(blank_lines.len(), 2);
+        assert_eq!(blank_lines.row(1).map(Row::as_str), Some("b"));
+        assert_eq!(blank_lines.backspace(&Position { x: 0, y: 1 }), Position { x: 0, y: 0 });
+        assert_eq!(blank_lines.len(), 1);
+        assert_eq!(blank_lines.row(0).map(Row::as_str), Some("b"));
+    }
+
+    #[test]
+    fn insert_str_splits_rows_on_any_line_break() {
+        let mut document = Document::default();
+        assert_eq!(document.insert_str(&Position::default(), "a\r\nb\rc\nd"), Position { x: 1, y: 3 });
+        assert_eq!(document.len(), 4);
+        for (y, text) in ["a", "b", "c", "d"].into_iter().enumerate() {
+            assert_eq!(document.row(y).map(Row::as_str), Some(text));
+        }
+        assert_eq!(document.insert_str(&Position { x: 1, y: 3 }, "\r\r\n"), Position { x: 0, y: 5 });
+        assert_eq!(document.len(), 6);
+    }
+
+    #[test]
+    fn typing_on_virtual_line_creates_row() {
+        let mut document = Document::default();
+        assert_eq!(document.line_count_with_virtual(), 1);
+        document.insert_str(&Position::default(), "first");
+        assert_eq!(document.line_count_with_virtual(), 2);
+        document.insert(&Position { x: 0, y: 1 }, 'x');
+        assert_eq!(document.len(), 2);
+        assert_eq!(document.row(1).map(Row::as_str), Some("x"));
+        document.insert(&Position { x: 1, y: 1 }, '\n');
+        assert_eq!(document.len(), 3);
+        assert_eq!(document.line_count_with_virtual(), 3);
+    }
+
+    #[test]
+    fn insert_past_end_of_row_appends() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "abc\ndef");
+        document.insert(&Position { x: 100, y: 0 }, '!');
+        assert_eq!(document.row(0).map(Row::as_str), Some("abc!"));
+        document.insert(&Position { x: 100, y: 0 }, '\n');
+        assert_eq!(document.row(0).map(Row::as_str), Some("abc!"));
+        assert_eq!(document.row(1).map(Row::as_str), Some(""));
+        assert_eq!(document.row(2).map(Row::as_str), Some("def"));
+    }
+
+    #[test]
+    fn type_char_replaces_selection() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "abcdef");
+        document.set_selection(&Position { x: 4, y: 0 }, &Position { x: 2, y: 0 });
+        assert_eq!(document.selection(), Some((Position { x: 2, y: 0 }, Position { x: 4, y: 0 })));
+        assert_eq!(document.type_char(&Position { x: 4, y: 0 }, 'z'), Position { x: 3, y: 0 });
+        assert_eq!(document.row(0).map(Row::as_str), Some("abzef"));
+        assert_eq!(document.selection(), None);
+        assert_eq!(document.type_char(&Position { x: 3, y: 0 }, 'y'), Position { x: 4, y: 0 });
+        assert_eq!(document.row(0).map(Row::as_str), Some("abzyef"));
+        assert_eq!(document.undo(), Some(Position { x: 3, y: 0 }));
+        assert_eq!(document.row(0).map(Row::as_str), Some("abzef"));
+        assert_eq!(document.undo(), Some(Position { x: 2, y: 0 }));
+        assert_eq!(document.row(0).map(Row::as_str), Some("abcdef"));
+    }
+
+    #[test]
+    fn select_all_and_select_line() {
+        let mut document = document(3);
+        document.select_all();
+        assert_eq!(
+            document.selected_text().as_deref(),
+            Some("line number 0\nline number 1\nline number 2")
+        );
+        document.select_line(1);
+        assert_eq!(document.selected_text().as_deref(), Some("line number 1\n"));
+        document.select_line(2);
+        assert_eq!(document.selected_text().as_deref(), Some("line number 2"));
+        document.clear_selection();
+        assert_eq!(document.selected_text(), None);
+    }
+
+    #[test]
+    fn type_char_auto_pairs_quotes_outside_words() {
+        let mut document = Document {
+            file_type: FileType::from("main.rs"),
+            ..Document::default()
+        };
+        document.set_auto_pair(true);
+        document.insert_str(&Position::default(), "don");
+        assert_eq!(document.type_char(&Position { x: 3, y: 0 }, '\''), Position { x: 4, y: 0 });
+        assert_eq!(document.row(0).map(Row::as_str), Some("don'"));
+        document.insert_str(&Position { x: 4, y: 0 }, " ");
+        assert_eq!(document.type_char(&Position { x: 5, y: 0 }, '\''), Position { x: 6, y: 0 });
+        assert_eq!(document.row(0).map(Row::as_str), Some("don' ''"));
+        assert_eq!(document.type_char(&Position { x: 6, y: 0 }, '\''), Position { x: 7, y: 0 });
+        assert_eq!(document.row(0).map(Row::as_str), Some("don' ''"));
+
+        let mut brackets = Document::default();
+        brackets.set_auto_pair(true);
+        assert_eq!(brackets.type_char(&Position::default(), '('), Position { x: 1, y: 0 });
+        assert_eq!(brackets.type_char(&Position { x: 1, y: 0 }, '"'), Position { x: 2, y: 0 });
+        assert_eq!(brackets.row(0).map(Row::as_str), Some("(\"\")"));
+        assert_eq!(brackets.type_char(&Position { x: 2, y: 0 }, 'x'), Position { x: 3, y: 0 });
+        assert_eq!(brackets.type_char(&Position { x: 3, y: 0 }, '"'), Position { x: 4, y: 0 });
+        assert_eq!(brackets.type_char(&Position { x: 4, y: 0 }, ')'), Position { x: 5, y: 0 });
+        assert_eq!(brackets.row(0).map(Row::as_str), Some("(\"x\")"));
+        assert_eq!(brackets.type_char(&Position { x: 0, y: 0 }, '"'), Position { x: 1, y: 0 });
+        assert_eq!(brackets.row(0).map(Row::as_str), Some("\"\"(\"x\")"));
+    }
+
+    #[test]
+    fn type_char_overwrites_in_overwrite_mode() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "abc");
+        document.set_overwrite(true);
+        assert_eq!(document.type_char(&Position { x: 1, y: 0 }, 'X'), Position { x: 2, y: 0 });
+        assert_eq!(document.row(0).map(Row::as_str), Some("aXc"));
+        assert_eq!(document.undo(), Some(Position { x: 1, y: 0 }));
+        assert_eq!(document.row(0).map(Row::as_str), Some("abc"));
+        assert_eq!(document.type_char(&Position { x: 1, y: 0 }, 'X'), Position { x: 2, y: 0 });
+        assert_eq!(document.type_char(&Position { x: 3, y: 0 }, 'd'), Position { x: 4, y: 0 });
+        assert_eq!(document.row(0).map(Row::as_str), Some("aXcd"));
+        assert_eq!(document.type_char(&Position { x: 1, y: 0 }, '\n'), Position { x: 0, y: 1 });
+        assert_eq!(document.row(0).map(Row::as_str), Some("a"));
+        assert_eq!(document.row(1).map(Row::as_str), Some("Xcd"));
+    }
+
+    #[test]
+    fn lint_flags_tabs_for_alignment() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "code\talign\n\tcode\n\t\n\tx = 1;\t\t// y");
+        assert_eq!(
+            document.lint(),
+            vec![
+                (Position { x: 4, y: 0 }, Lint::TabForAlignment),
+                (Position { x: 7, y: 3 }, Lint::TabForAlignment),
+                (Position { x: 8, y: 3 }, Lint::TabForAlignment),
+            ]
+        );
+    }
+
+    #[test]
+    fn indentation_violations_follow_expected_style() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "fn f() {\n    a();\n\tb();\n      c();\n  \n}");
+        document.set_tab_width(4);
+        assert_eq!(document.indentation_violations(IndentStyle::Spaces), vec![2, 3]);
+        assert_eq!(document.indentation_violations(IndentStyle::Tabs), vec![1, 3]);
+    }
+
+    #[test]
+    fn insert_indent_aligns_to_tab_stop() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "ab");
+        document.set_indent_style(IndentStyle::Spaces);
+        document.set_tab_width(4);
+        let position = document.insert_indent(&Position { x: 2, y: 0 });
+        assert_eq!(position, Position { x: 4, y: 0 });
+        assert_eq!(document.row(0).map(Row::as_str), Some("ab  "));
+
+        document.set_indent_style(IndentStyle::Tabs);
+        let position = document.insert_indent(&position);
+        assert_eq!(position, Position { x: 5, y: 0 });
+        assert_eq!(document.row(0).map(Row::as_str), Some("ab  \t"));
+
+        document.set_indent_style(IndentStyle::Spaces);
+        document.insert_str(&position, "c");
+        assert_eq!(document.insert_indent(&Position { x: 6, y: 0 }), Position { x: 9, y: 0 });
+        assert_eq!(document.row(0).map(Row::as_str), Some("ab  \tc   "));
+    }
+
+    #[test]
+    fn remove_indent_only_touches_leading_whitespace() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "      code    here\n\tcode\n\t    code");
+        document.set_tab_width(4);
+        let position = document.remove_indent(&Position { x: 6, y: 0 });
+        assert_eq!(position, Position { x: 4, y: 0 });
+        let position = document.remove_indent(&position);
+        assert_eq!(position, Position { x: 0, y: 0 });
+        assert_eq!(document.remove_indent(&Position { x: 8, y: 0 }), Position { x: 8, y: 0 });
+        assert_eq!(document.row(0).map(Row::as_str), Some("code    here"));
+        assert_eq!(document.remove_indent(&Position { x: 1, y: 1 }), Position { x: 0, y: 1 });
+        assert_eq!(document.row(1).map(Row::as_str), Some("code"));
+        assert_eq!(document.remove_indent(&Position { x: 5, y: 2 }), Position { x: 1, y: 2 });
+        assert_eq!(document.row(2).map(Row::as_str), Some("\tcode"));
+    }
+
+    #[test]
+    fn restore_recovers_snapshot() {
+        let mut document = document(3);
+        let snapshot = document.snapshot();
+        document.insert_str(&Position { x: 2, y: 1 }, "new\nrows");
+        document.delete(&Position::default());
+        document.kill_line(&Position { x: 0, y: 2 });
+        assert!(document.is_dirty());
+
+        document.restore(snapshot);
+        assert!(!document.is_dirty());
+        assert_eq!(document.len(), 3);
+        for index in 0..3 {
+            let expected = format!("line number {index}");
+            assert_eq!(document.row(index).map(Row::as_str), Some(expected.as_str()));
+        }
+    }
+
+    #[test]
+    fn find_with_options_wraps_around() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "a needle\nhay\nhay\nhay needle hay");
+        let near_end = Position { x: 4, y: 3 };
+        let forward = document.find_with_options("a needle", &near_end, SearchDirection::Forward, true, false);
+        assert_eq!(forward, Some((Position { x: 0, y: 0 }, true)));
+        assert_eq!(document.find_with_options("a needle", &near_end, SearchDirection::Forward, false, false), None);
+        let found = document.find_with_options("needle", &near_end, SearchDirection::Forward, true, false);
+        assert_eq!(found, Some((Position { x: 4, y: 3 }, false)));
+
+        let near_start = Position { x: 1, y: 0 };
+        let backward = document.find_with_options("needle", &near_start, SearchDirection::Backward, true, false);
+        assert_eq!(backward, Some((Position { x: 4, y: 3 }, true)));
+        assert_eq!(document.find_with_options("missing", &near_start, SearchDirection::Backward, true, false), None);
+    }
+
+    #[test]
+    fn search_history_recalls_most_recent_first() {
+        let mut document = Document::default();
+        for query in ["one", "two", "two", "", "three"] {
+            document.push_search(query);
+        }
+        assert_eq!(document.history_next(), None);
+        assert_eq!(document.history_prev(), Some("three"));
+        assert_eq!(document.history_prev(), Some("two"));
+        assert_eq!(document.history_prev(), Some("one"));
+        assert_eq!(document.history_prev(), None);
+        assert_eq!(document.history_next(), Some("two"));
+        assert_eq!(document.history_next(), Some("three"));
+        assert_eq!(document.history_next(), None);
+        document.push_search("one");
+        assert_eq!(document.history_prev(), Some("one"));
+        assert_eq!(document.history_prev(), Some("three"));
+    }
+
+    #[test]
+    fn searches_on_empty_documents_and_rows() {
+        let mut empty = Document::default();
+        let start = Position::default();
+        assert_eq!(empty.find("a", &start, SearchDirection::Forward, false, None), None);
+        assert_eq!(empty.find("a", &start, SearchDirection::Backward, false, None), None);
+        assert_eq!(empty.find_all("a", false, None), Vec::new());
+        assert_eq!(empty.count_matches("a", false), 0);
+        assert_eq!(empty.replace_all("a", "b", false), 0);
+        assert_eq!(empty.find_multiline("a", &start, SearchDirection::Forward), None);
+        assert!(empty.search_results("a", true).is_empty());
+        assert!(!empty.is_dirty());
+
+        let mut blank = Document::from_reader("\n\na\n\n".as_bytes()).expect("Failed reading document");
+        assert_eq!(blank.find("a", &start, SearchDirection::Forward, false, None), Some(Position { x: 0, y: 2 }));
+        assert_eq!(blank.find("a", &Position { x: 0, y: 3 }, SearchDirection::Backward, false, None), Some(Position { x: 0, y: 2 }));
+        assert_eq!(blank.count_matches("a", true), 1);
+        assert_eq!(blank.find("", &start, SearchDirection::Forward, false, None), None);
+        assert_eq!(blank.find("", &Position { x: 0, y: 3 }, SearchDirection::Backward, false, None), None);
+        assert_eq!(blank.find_all("", false, None), Vec::new());
+        assert_eq!(blank.count_matches("", false), 0);
+        assert_eq!(blank.find_multiline("", &start, SearchDirection::Forward), None);
+        assert!(blank.search_results("", true).is_empty());
+        assert_eq!(blank.replace_all("", "x", false), 0);
+        assert_eq!(blank.replace_all("a", "", false), 1);
+        assert_eq!(blank.count_matches("a", false), 0);
+        assert_eq!(blank.len(), 4);
+    }
+
+    #[test]
+    fn find_multiline_matches_across_rows() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "xa\nb\na\nbc");
+        let forward = |at: Position| document.find_multiline("a\nb", &at, SearchDirection::Forward).map(|(position, _)| position);
+        assert_eq!(forward(Position::default()), Some(Position { x: 1, y: 0 }));
+        assert_eq!(forward(Position { x: 2, y: 0 }), Some(Position { x: 0, y: 2 }));
+        assert_eq!(forward(Position { x: 1, y: 2 }), None);
+        let backward = document.find_multiline("a\nb", &Position { x: 0, y: 2 }, SearchDirection::Backward);
+        assert_eq!(backward, Some((Position { x: 1, y: 0 }, 3)));
+        assert_eq!(document.find_multiline("b\na\nbc", &Position::default(), SearchDirection::Forward), Some((Position { x: 0, y: 1 }, 6)));
+        assert_eq!(document.find_multiline("", &Position::default(), SearchDirection::Forward), None);
+
+        let crlf = Document::from_reader(Cursor::new(&b"one\r\ntwo\r\n"[..])).expect("Failed reading document");
+        assert_eq!(crlf.find_multiline("e\nt", &Position::default(), SearchDirection::Forward), Some((Position { x: 2, y: 0 }, 3)));
+    }
+
+    #[test]
+    fn find_backward_skips_match_at_cursor() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "foo x foo\nbar foo foo");
+        let backward = |x: usize, y: usize| document.find("foo", &Position { x, y }, SearchDirection::Backward, false, None);
+        assert_eq!(backward(8, 1), Some(Position { x: 4, y: 1 }));
+        assert_eq!(backward(4, 1), Some(Position { x: 6, y: 0 }));
+        assert_eq!(backward(6, 0), Some(Position { x: 0, y: 0 }));
+        assert_eq!(backward(0, 0), None);
+        assert_eq!(backward(9, 1), Some(Position { x: 8, y: 1 }));
+        assert_eq!(backward(0, 2), Some(Position { x: 8, y: 1 }));
+    }
+
+    #[test]
+    fn find_all_whole_word() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "in inside begin in\nwithin in");
+        assert_eq!(
+            document.find_all("in", true, None),
+            vec![Position { x: 0, y: 0 }, Position { x: 16, y: 0 }, Position { x: 7, y: 1 }]
+        );
+        assert_eq!(document.find_all("in", false, None).len(), 6);
+        let backward = document.find("in", &Position { x: 7, y: 1 }, SearchDirection::Backward, true, None);
+        assert_eq!(backward, Some(Position { x: 16, y: 0 }));
+    }
+
+    #[test]
+    fn equal_content_compares_equal() {
+        let mut typed = Document::default();
+        typed.insert_str(&Position::default(), "fn main() {}\n");
+        typed.highlight(&None, None);
+        let read = Document::from_reader(Cursor::new(&b"fn main() {}\n\n"[..])).expect("Failed reading document");
+        assert!(typed == read);
+        assert_eq!(typed.content_hash(), read.content_hash());
+
+        typed.insert(&Position::default(), ' ');
+        assert!(typed != read);
+        assert_ne!(typed.content_hash(), read.content_hash());
+    }
+
+    #[test]
+    fn move_range_reorders_rows_in_one_undo() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "zero\none\ntwo\nthree\nfour");
+        let after_rows = document.move_range(&Position { x: 0, y: 1 }, &Position { x: 0, y: 2 }, &Position { x: 0, y: 4 });
+        assert_eq!(after_rows, Position { x: 0, y: 4 });
+        let rows: Vec<&str> = (0..document.len()).filter_map(|y| document.row(y).map(Row::as_str)).collect();
+        assert_eq!(rows, vec!["zero", "two", "three", "one", "four"]);
+
+        let after_word = document.move_range(&Position { x: 0, y: 3 }, &Position { x: 3, y: 3 }, &Position { x: 3, y: 0 });
+        assert_eq!(after_word, Position { x: 6, y: 0 });
+        assert_eq!(document.row(0).map(Row::as_str), Some("zeroneo"));
+        assert_eq!(document.move_range(&Position { x: 0, y: 0 }, &Position { x: 4, y: 0 }, &Position { x: 2, y: 0 }), Position { x: 2, y: 0 });
+        assert_eq!(document.row(0).map(Row::as_str), Some("zeroneo"));
+
+        assert_eq!(document.undo(), Some(Position { x: 3, y: 0 }));
+        assert_eq!(document.row(0).map(Row::as_str), Some("zero"));
+        assert_eq!(document.row(3).map(Row::as_str), Some("one"));
+        assert_eq!(document.undo(), Some(Position { x: 0, y: 3 }));
+        assert_eq!(document.row(1).map(Row::as_str), Some("one"));
+    }
+
+    #[test]
+    fn transform_range_changes_only_the_range() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "one two\nthree four");
+        let end = document.transform_range(&Position { x: 4, y: 0 }, &Position { x: 5, y: 1 }, str::to_uppercase);
+        assert_eq!(end, Position { x: 5, y: 1 });
+        assert_eq!(document.row(0).map(Row::as_str), Some("one TWO"));
+        assert_eq!(document.row(1).map(Row::as_str), Some("THREE four"));
+        assert_eq!(document.undo(), Some(Position { x: 4, y: 0 }));
+        assert_eq!(document.row(0).map(Row::as_str), Some("one two"));
+        assert_eq!(document.row(1).map(Row::as_str), Some("three four"));
+    }
+
+    #[test]
+    fn max_line_width_follows_edits() {
+        let mut document = document(3);
+        assert_eq!(document.max_line_width(), 13);
+        document.insert_str(&Position { x: 0, y: 3 }, "\ta much longer line");
+        assert_eq!(document.max_line_width(), 22);
+        document.set_tab_width(8);
+        assert_eq!(document.max_line_width(), 26);
+        document.kill_line(&Position { x: 0, y: 3 });
+        assert_eq!(document.max_line_width(), 13);
+        document.delete(&Position { x: 13, y: 1 });
+        assert_eq!(document.max_line_width(), 26);
+    }
+
+    #[test]
+    fn indent_guides_continue_across_blank_rows() {
+        let mut document = Document::default();
+        document.set_tab_width(4);
+        document.insert_str(&Position::default(), "fn main() {\n    if x {\n        a();\n\n        b();\n    }\n}\n");
+        assert_eq!(document.indent_guides(0), Vec::<usize>::new());
+        assert_eq!(document.indent_guides(1), vec![0]);
+        assert_eq!(document.indent_guides(2), vec![0, 4]);
+        assert_eq!(document.indent_guides(3), vec![0, 4]);
+        assert_eq!(document.indent_guides(5), vec![0]);
+        assert_eq!(document.indent_guides(7), Vec::<usize>::new());
+        assert_eq!(document.indent_guides(100), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn column_to_x_accounts_for_tabs() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "\tX\n\u{4e2d}\u{6587}!");
+        document.set_tab_width(4);
+        assert_eq!(document.column_to_x(0, 4), 1);
+        assert_eq!(document.column_to_x(0, 0), 0);
+        assert_eq!(document.column_to_x(0, 3), 0);
+        assert_eq!(document.column_to_x(0, 8), 2);
+        assert_eq!(document.column_to_x(1, 3), 1);
+        assert_eq!(document.column_to_x(1, 4), 2);
+        assert_eq!(document.visual_column(&Position { x: 2, y: 1 }), 4);
+        assert_eq!(document.column_to_x(2, 5), 0);
+    }
+
+    #[test]
+    fn toggle_block_comment_round_trips() {
+        let mut document = Document {
+            file_type: FileType::from("main.c"),
+            ..Document::default()
+        };
+        document.insert_str(&Position::default(), "int a;\nint b;\nint c;");
+        let start = Position { x: 4, y: 0 };
+        let end = document.toggle_block_comment(&start, &Position { x: 3, y: 1 });
+        assert_eq!(end, Position { x: 5, y: 1 });
+        assert_eq!(document.row(0).map(Row::as_str), Some("int /*a;"));
+        assert_eq!(document.row(1).map(Row::as_str), Some("int*/ b;"));
+
+        let end = document.toggle_block_comment(&start, &end);
+        assert_eq!(end, Position { x: 3, y: 1 });
+        assert_eq!(document.row(0).map(Row::as_str), Some("int a;"));
+        assert_eq!(document.row(1).map(Row::as_str), Some("int b;"));
+    }
+
+    #[test]
+    fn replace_all_does_not_overlap() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "aaaa\naaa");
+        assert_eq!(document.replace_all("aa", "b", false), 3);
+        assert_eq!(document.row(0).map(Row::as_str), Some("bb"));
+        assert_eq!(document.row(1).map(Row::as_str), Some("ba"));
+        assert_eq!(document.replace_all("", "b", false), 0);
+        assert_eq!(document.replace_all("b", "bb", false), 3);
+        assert_eq!(document.row(0).map(Row::as_str), Some("bbbb"));
+    }
+
+    #[test]
+    fn scroll_offset_keeps_margin_and_clamps() {
+        let document = document(100);
+        assert_eq!(document.scroll_offset(12, 0, 10, 3), 6);
+        assert_eq!(document.scroll_offset(8, 6, 10, 3), 5);
+        assert_eq!(document.scroll_offset(5, 6, 10, 3), 2);
+        assert_eq!(document.scroll_offset(1, 6, 10, 3), 0);
+        assert_eq!(document.scroll_offset(98, 80, 10, 3), 90);
+        assert_eq!(document.scroll_offset(100, 80, 10, 3), 91);
+        assert_eq!(document.scroll_offset(4, 0, 10, 30), 0);
+    }
+
+    #[test]
+    fn search_results_carry_row_text() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "let Foo = 1;\nnothing\nfoo(foo)");
+        let results = document.search_results("foo", false);
+        assert_eq!(results.len(), 3);
+        let first = results.first().expect("Expected a result");
+        assert_eq!(first.position, Position { x: 4, y: 0 });
+        assert_eq!(first.row_text, "let Foo = 1;");
+        assert_eq!(first.range, (4, 7));
+        let last = results.last().expect("Expected a result");
+        assert_eq!(last.position, Position { x: 4, y: 2 });
+        assert_eq!(last.row_text, "foo(foo)");
+        assert_eq!(document.search_results("foo", true).len(), 2);
+    }
+
+    #[test]
+    fn undo_coalesces_edits_within_window() {
+        let mut document = Document::default();
+        document.set_coalesce_window(Duration::from_secs(30));
+        document.insert_str(&Position::default(), "one");
+        document.insert_str(&Position { x: 3, y: 0 }, " two");
+        assert_eq!(document.undo(), Some(Position::default()));
+        assert!(document.is_empty());
+        assert_eq!(document.undo(), None);
+        assert_eq!(document.redo(), Some(Position::default()));
+        assert_eq!(document.row(0).map(Row::as_str), Some("one two"));
+
+        document.insert(&Position::default(), '>');
+        assert_eq!(document.undo(), Some(Position::default()));
+        assert_eq!(document.row(0).map(Row::as_str), Some("one two"));
+    }
+
+    #[test]
+    fn undo_splits_edits_at_group_end() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "one");
+        document.end_undo_group();
+        document.insert_str(&Position { x: 3, y: 0 }, " two");
+        assert_eq!(document.undo(), Some(Position { x: 3, y: 0 }));
+        assert_eq!(document.row(0).map(Row::as_str), Some("one"));
+        assert!(document.is_dirty());
+        assert_eq!(document.undo(), Some(Position::default()));
+        assert!(document.is_empty());
+        assert!(!document.is_dirty());
+    }
+
+    #[test]
+    fn as_one_edit_on_full_undo_stack() {
+        let mut document = Document::default();
+        for x in 0..UNDO_STACK_SIZE {
+            document.insert(&Position { x, y: 0 }, 'a');
+            document.end_undo_group();
+        }
+        assert_eq!(document.undo_stack.len(), UNDO_STACK_SIZE);
+        document.replace_range(&Position { x: 0, y: 0 }, &Position { x: 2, y: 0 }, "b");
+        assert_eq!(document.undo_stack.len(), UNDO_STACK_SIZE);
+        assert_eq!(document.undo(), Some(Position::default()));
+        assert_eq!(document.row(0).map(Row::len), Some(UNDO_STACK_SIZE));
+        assert_eq!(document.undo(), Some(Position { x: UNDO_STACK_SIZE.saturating_sub(1), y: 0 }));
+        assert_eq!(document.row(0).map(Row::len), Some(UNDO_STACK_SIZE.saturating_sub(1)));
+    }
+
+    #[test]
+    fn undo_past_save_is_dirty() {
+        let path = std::env::temp_dir().join("hammare_undo_past_save.txt");
+        fs::write(&path, "one\n").expect("Failed writing file");
+        let mut document = Document::open(path.to_str().expect("Failed converting path")).expect("Failed opening file");
+        document.insert_str(&Position { x: 3, y: 0 }, " two");
+        document.save().expect("Failed saving file");
+        assert!(!document.is_dirty());
+        assert_eq!(document.undo(), Some(Position { x: 3, y: 0 }));
+        assert!(document.is_dirty());
+        assert_eq!(document.redo(), Some(Position { x: 3, y: 0 }));
+        assert!(!document.is_dirty());
+        fs::remove_file(&path).expect("Failed removing file");
+    }
+
+    #[test]
+    fn cancelled_search_returns_early() {
+        let document = document(10);
+        let cancel = AtomicBool::new(false);
+        let found = document.find_all_with("line", false, Some(&cancel), |y| {
+            if y == 4 {
+                cancel.store(true, Ordering::Relaxed);
+            }
+        });
+        assert_eq!(found.len(), 4);
+        let at = Position::default();
+        assert_eq!(document.find("9", &at, SearchDirection::Forward, false, Some(&cancel)), None);
+        cancel.store(false, Ordering::Relaxed);
+        assert_eq!(document.find("9", &at, SearchDirection::Forward, false, Some(&cancel)), Some(Position { x: 12, y: 9 }));
+    }
+
+    #[test]
+    fn byte_offset_to_position_counts_line_endings() {
+        let document = Document::from_reader(Cursor::new("fn main() {\r\n    let caf\u{e9} = 1;\r\n}\r\n".as_bytes())).expect("Failed reading document");
+        assert_eq!(document.byte_offset_to_position(0), Some(Position { x: 0, y: 0 }));
+        assert_eq!(document.byte_offset_to_position(11), Some(Position { x: 11, y: 0 }));
+        assert_eq!(document.byte_offset_to_position(12), Some(Position { x: 11, y: 0 }));
+        assert_eq!(document.byte_offset_to_position(17), Some(Position { x: 4, y: 1 }));
+        assert_eq!(document.byte_offset_to_position(24), Some(Position { x: 11, y: 1 }));
+        assert_eq!(document.byte_offset_to_position(25), Some(Position { x: 11, y: 1 }));
+        assert_eq!(document.byte_offset_to_position(26), Some(Position { x: 12, y: 1 }));
+        assert_eq!(document.byte_offset_to_position(33), Some(Position { x: 0, y: 2 }));
+        assert_eq!(document.byte_offset_to_position(34), Some(Position { x: 1, y: 2 }));
+        assert_eq!(document.byte_offset_to_position(36), Some(Position { x: 0, y: 3 }));
+        assert_eq!(document.byte_offset_to_position(37), None);
+    }
+
+    #[test]
+    fn position_to_byte_offset_round_trips() {
+        let document = Document::from_reader(Cursor::new("fn main() {\r\n    let caf\u{e9} = 1;\r\n}\r\n".as_bytes())).expect("Failed reading document");
+        for (x, y) in [(0, 0), (5, 0), (11, 0), (0, 1), (11, 1), (12, 1), (17, 1), (0, 2), (1, 2), (0, 3)] {
+            let position = Position { x, y };
+            let offset = document.position_to_byte_offset(&position);
+            assert_eq!(document.byte_offset_to_position(offset), Some(position));
+        }
+        assert_eq!(document.position_to_byte_offset(&Position { x: 12, y: 1 }), 26);
+        assert_eq!(document.position_to_byte_offset(&Position { x: 40, y: 0 }), 11);
+        assert_eq!(document.position_to_byte_offset(&Position { x: 3, y: 40 }), 36);
+    }
+
+    #[test]
+    fn context_clamps_to_document() {
+        let document = document(5);
+        let rows = |at: Position, before, after| -> Vec<usize> {
+            document.context(&at, before, after).into_iter().map(|(y, _)| y).collect()
+        };
+        assert_eq!(rows(Position { x: 0, y: 1 }, 3, 1), vec![0, 1, 2]);
+        assert_eq!(rows(Position { x: 0, y: 3 }, 1, 10), vec![2, 3, 4]);
+        assert_eq!(rows(Position { x: 0, y: 9 }, 1, 1), Vec::<usize>::new());
+        assert_eq!(document.context(&Position::default(), 0, 0), vec![(0, "line number 0".to_owned())]);
+    }
+
+    #[test]
+    fn status_info_reports_visual_column() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "first\n\tab");
+        let info = document.status_info(&Position { x: 2, y: 1 });
+        assert_eq!(info.line, 2);
+        assert_eq!(info.column, 6);
+        assert_eq!(info.lines, 2);
+        assert!(info.is_dirty);
+        assert_eq!(info.line_ending, LineEnding::Lf);
+        assert_eq!(document.status_info(&Position::default()).column, 1);
+    }
+
+    #[test]
+    fn crlf_round_trips() {
+        let mut document = Document::from_reader(Cursor::new(&b"one\r\ntwo\r\n"[..])).expect("Failed reading document");
+        assert_eq!(document.line_ending(), LineEnding::CrLf);
+        assert_eq!(document.row(0).map(Row::as_str), Some("one"));
+        let mut on_disk = Vec::new();
+        document.write_rows(&mut on_disk, 0).expect("Failed writing rows");
+        assert_eq!(on_disk, b"one\r\ntwo\r\n");
+
+        document.insert(&Position { x: 3, y: 1 }, '!');
+        let mut file = Cursor::new(on_disk);
+        let end = document.write_incremental(&mut file).expect("Failed writing rows");
+        assert_eq!(end, Some(11));
+        assert_eq!(file.into_inner(), b"one\r\ntwo!\r\n");
+    }
+
+    #[test]
+    fn set_line_ending_rewrites_every_row() {
+        let path = std::env::temp_dir().join("hammare_set_line_ending.txt");
+        let file_name = path.to_str().expect("Failed converting path").to_owned();
+        fs::write(&path, "one\r\ntwo\r\n").expect("Failed writing file");
+        let mut document = Document::open(&file_name).expect("Failed opening file");
+        document.set_line_ending(LineEnding::CrLf);
+        assert!(!document.is_dirty());
+        document.set_line_ending(LineEnding::Lf);
+        assert!(document.is_dirty());
+        document.set_line_ending(LineEnding::CrLf);
+        assert!(!document.is_dirty());
+        assert_eq!(document.undo(), Some(Position::default()));
+        assert_eq!(document.line_ending(), LineEnding::Lf);
+        assert!(document.is_dirty());
+        document.save().expect("Failed saving file");
+        assert_eq!(fs::read(&path).expect("Failed reading file"), b"one\ntwo\n");
+
+        document.set_line_ending(LineEnding::CrLf);
+        document.insert(&Position { x: 3, y: 1 }, '!');
+        document.save_incremental().expect("Failed saving file");
+        assert_eq!(fs::read(&path).expect("Failed reading file"), b"one\r\ntwo!\r\n");
+        fs::remove_file(&path).expect("Failed removing file");
+    }
+
+    #[test]
+    fn save_incremental_after_undoing_line_ending() {
+        let path = std::env::temp_dir().join("hammare_undo_line_ending.txt");
+        fs::write(&path, "one\r\ntwo\r\nthree\r\n").expect("Failed writing file");
+        let mut document = Document::open(path.to_str().expect("Failed converting path")).expect("Failed opening file");
+        document.set_line_ending(LineEnding::Lf);
+        document.save().expect("Failed saving file");
+        assert_eq!(document.undo(), Some(Position::default()));
+        document.insert(&Position { x: 5, y: 2 }, '!');
+        document.save_incremental().expect("Failed saving file");
+        assert_eq!(fs::read(&path).expect("Failed reading file"), b"one\r\ntwo\r\nthree!\r\n");
+        fs::remove_file(&path).expect("Failed removing file");
+    }
+
+    #[test]
+    fn open_unknown_extension_as_plain_text() {
+        let path = std::env::temp_dir().join("hammare_plain_text.xyz");
+        fs::write(&path, "fn main() { let x = 42; } // \"text\"  \n").expect("Failed writing file");
+        let mut document = Document::open(path.to_str().expect("Failed converting path")).expect("Failed opening file");
+        assert_eq!(document.file_type(), "Plain Text");
+        document.highlight(&None, None);
+        let row = document.row(0).expect("Failed getting row");
+        assert_eq!(row.highlighting().len(), row.len());
+        assert!(row.highlighting().iter().all(|hl_type| *hl_type == highlighting::Type::None));
+        fs::remove_file(&path).expect("Failed removing file");
+    }
+
+    #[test]
+    fn open_detects_file_type_from_shebang() {
+        let path = std::env::temp_dir().join("hammare_shebang_script");
+        fs::write(&path, "#!/usr/bin/env python3\nprint(1)\n").expect("Failed writing file");
+        let document = Document::open(path.to_str().expect("Failed converting path")).expect("Failed opening file");
+        assert_eq!(document.file_type(), "Python");
+        fs::remove_file(&path).expect("Failed removing file");
+    }
+
+    #[test]
+    fn save_detects_file_type_from_content() {
+        let path = std::env::temp_dir().join("hammare_save_detects_type");
+        let mut document = Document {
+            file_name: Some(path.to_str().expect("Failed converting path").to_owned()),
+            ..Document::default()
+        };
+        document.insert_str(&Position::default(), "#!/bin/sh\necho \"hi\"");
+        document.highlight(&None, None);
+        assert_eq!(document.row(1).and_then(|row| row.highlight_at(5)), Some(highlighting::Type::None));
+
+        document.save().expect("Failed saving file");
+        assert_eq!(document.file_type(), "Plain Text");
+        document.set_detect_type_from_content(true);
+        document.save().expect("Failed saving file");
+        assert_eq!(document.file_type(), "Shell");
+        assert_eq!(document.row(1).and_then(|row| row.highlight_at(5)), Some(highlighting::Type::String));
+
+        document.set_file_type(FileType::from("main.py"));
+        document.save().expect("Failed saving file");
+        assert_eq!(document.file_type(), "Python");
+        fs::remove_file(&path).expect("Failed removing file");
+    }
+
+    #[test]
+    fn save_returns_bytes_written() {
+        let path = std::env::temp_dir().join("hammare_save_bytes.txt");
+        let mut document = Document::from_reader(Cursor::new("caf\u{e9}\r\nline two\r\n".as_bytes())).expect("Failed reading document");
+        document.file_name = Some(path.to_str().expect("Failed converting path").to_owned());
+        let written = document.save().expect("Failed saving file");
+        assert_eq!(written, 17);
+        let on_disk = fs::metadata(&path).expect("Failed reading metadata").len();
+        assert_eq!(u64::try_from(written).ok(), Some(on_disk));
+        fs::remove_file(&path).expect("Failed removing file");
+    }
+
+    #[test]
+    fn save_hard_wraps_prose_only() {
+        let text = "  Hard wrapping keeps every row of this paragraph within forty columns\n\
+                    except https://example.com/a/very/long/unbreakable/address here";
+        let path = std::env::temp_dir().join("hammare_hard_wrap.txt");
+        fs::write(&path, text).expect("Failed writing file");
+        let mut document = Document::open(path.to_str().expect("Failed converting path")).expect("Failed opening file");
+        document.set_hard_wrap_column(Some(40));
+        document.save().expect("Failed saving file");
+        let saved = fs::read_to_string(&path).expect("Failed reading file");
+        assert_eq!(
+            saved.lines().collect::<Vec<_>>(),
+            vec![
+                "  Hard wrapping keeps every row of this",
+                "  paragraph within forty columns",
+                "except",
+                "https://example.com/a/very/long/unbreakable/address",
+                "here",
+            ]
+        );
+        assert!(saved.lines().all(|line| line.len() <= 40 || !line.contains(' ')));
+        fs::remove_file(&path).expect("Failed removing file");
+
+        let code = std::env::temp_dir().join("hammare_hard_wrap.rs");
+        fs::write(&code, text).expect("Failed writing file");
+        let mut source = Document::open(code.to_str().expect("Failed converting path")).expect("Failed opening file");
+        source.set_hard_wrap_column(Some(40));
+        source.insert(&Position::default(), ' ');
+        source.save().expect("Failed saving file");
+        assert_eq!(source.len(), 2);
+        fs::remove_file(&code).expect("Failed removing file");
+    }
+
+    #[test]
+    fn strip_control_chars_keeps_tabs() {
+        let mut document = Document::from_reader("a\x07b\tc\r\n\x00\x0cd\r\n".as_bytes()).expect("Failed reading");
+        assert_eq!(document.strip_control_chars(), 3);
+        assert_eq!(document.row(0).map(Row::as_str), Some("ab\tc"));
+        assert_eq!(document.row(1).map(Row::as_str), Some("d"));
+        assert_eq!(document.line_ending(), LineEnding::CrLf);
+        assert!(document.is_dirty());
+        assert_eq!(document.strip_control_chars(), 0);
+        document.undo();
+        assert_eq!(document.row(0).map(Row::as_str), Some("a\x07b\tc"));
+    }
+
+    #[test]
+    fn clone_shares_rows_until_edited() {
+        let original = document(1000);
+        let mut copy = original.clone();
+        copy.insert(&Position { x: 0, y: 500 }, 'x');
+        for (y, (row, copied)) in original.rows.iter().zip(&copy.rows).enumerate() {
+            assert_eq!(Rc::ptr_eq(row, copied), y != 500);
+        }
+        assert_eq!(original.row(500).map(Row::as_str), Some("line number 500"));
+        assert_eq!(copy.row(500).map(Row::as_str), Some("xline number 500"));
+        assert!(!original.is_dirty());
+    }
+
+    #[test]
+    fn save_all_reports_each_document() {
+        let path = std::env::temp_dir().join("hammare_save_all.txt");
+        let mut writable = Document {
+            file_name: Some(path.to_str().expect("Failed converting path").to_owned()),
+            ..Document::default()
+        };
+        writable.insert(&Position::default(), 'a');
+        let unwritable = Document {
+            file_name: Some("/nonexistent/hammare_save_all.txt".to_owned()),
+            ..Document::default()
+        };
+        let mut docs = [writable, unwritable, Document::default()];
+        let results = save_all(&mut docs);
+        assert_eq!(results.len(), 3);
+        assert!(results.first().is_some_and(Result::is_ok));
+        assert!(results.get(1).is_some_and(Result::is_err));
+        assert!(results.get(2).is_some_and(|result| result.as_ref().is_err_and(|err| err.kind() == ErrorKind::NotFound)));
+        assert!(docs.first().is_some_and(|document| !document.is_dirty()));
+        assert_eq!(fs::read_to_string(&path).expect("Failed reading file"), "a\n");
+        fs::remove_file(&path).expect("Failed removing file");
+    }
+
+    #[test]
+    fn alternate_file_finds_existing_companion() {
+        let dir = std::env::temp_dir().join("hammare_alternate_file");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).expect("Failed removing directory");
+        }
+        fs::create_dir_all(dir.join("row")).expect("Failed creating directory");
+        for file in ["foo.c", "foo.h", "bar.c", "row.rs", "row/mod.rs"] {
+            fs::write(dir.join(file), "").expect("Failed writing file");
+        }
+        let alternate = |file: &str| {
+            let path = dir.join(file);
+            let document = Document {
+                file_name: path.to_str().map(str::to_owned),
+                ..Document::default()
+            };
+            document.alternate_file()
+        };
+        let path = |file: &str| dir.join(file).to_str().map(str::to_owned);
+        assert_eq!(alternate("foo.c"), path("foo.h"));
+        assert_eq!(alternate("foo.h"), path("foo.c"));
+        assert_eq!(alternate("bar.c"), None);
+        assert_eq!(alternate("row.rs"), path("row/mod.rs"));
+        assert_eq!(alternate("row/mod.rs"), path("row.rs"));
+        assert_eq!(Document::default().alternate_file(), None);
+        fs::remove_dir_all(&dir).expect("Failed removing directory");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn save_keeps_file_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join("hammare_save_permissions.txt");
+        fs::write(&path, "secret\n").expect("Failed writing file");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).expect("Failed setting permissions");
+        let mut document = Document::open(path.to_str().expect("Failed converting path")).expect("Failed opening file");
+        document.insert(&Position::default(), '!');
+        document.save().expect("Failed saving file");
+        let mode = fs::metadata(&path).expect("Failed reading metadata").permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        fs::remove_file(&path).expect("Failed removing file");
+    }
+
+    #[test]
+    fn open_at_parses_line_and_column() {
+        let path = std::env::temp_dir().join("hammare_open_at.txt");
+        fs::write(&path, "one\ntwo\nthree\n").expect("Failed writing file");
+        let file_name = path.to_str().expect("Failed converting path");
+        let (document, position) = Document::open_at(&format!("{file_name}:3:2")).expect("Failed opening file");
+        assert_eq!(position, Position { x: 1, y: 2 });
+        assert_eq!(document.file_name.as_deref(), Some(file_name));
+        let clamped = Document::open_at(&format!("{file_name}:10:10")).expect("Failed opening file").1;
+        assert_eq!(clamped, Position { x: 5, y: 2 });
+        let line_only = Document::open_at(&format!("{file_name}:2")).expect("Failed opening file").1;
+        assert_eq!(line_only, Position { x: 0, y: 1 });
+        fs::remove_file(&path).expect("Failed removing file");
+
+        assert_eq!(split_position_spec("C:\\src\\main.rs:4:2"), ("C:\\src\\main.rs", Some(4), Some(2)));
+        assert_eq!(split_position_spec("C:\\main.rs"), ("C:\\main.rs", None, None));
+        assert_eq!(split_position_spec("main.rs:"), ("main.rs:", None, None));
+    }
+
+    #[test]
+    fn open_expanded_resolves_environment_variables() {
+        let path = std::env::temp_dir().join("hammare_open_expanded.txt");
+        let file_name = path.to_str().expect("Failed converting path");
+        fs::write(&path, "expanded\n").expect("Failed writing file");
+        let document = Document::open_expanded(file_name).expect("Failed opening file");
+        assert_eq!(document.row(0).map(Row::as_str), Some("expanded"));
+        assert_eq!(document.file_name.as_deref(), Some(file_name));
+        fs::remove_file(&path).expect("Failed removing file");
+
+        let var = |name: &str| match name {
+            "HOME" => Some("/home/me".to_owned()),
+            "DIR" => Some("/tmp".to_owned()),
+            _ => None,
+        };
+        assert_eq!(expand_path("$DIR/a.txt", var), "/tmp/a.txt");
+        assert_eq!(expand_path("${DIR}/a.txt", var), "/tmp/a.txt");
+        assert_eq!(expand_path("$UNSET/a", var), "$UNSET/a");
+        assert_eq!(expand_path("${DIR", var), "${DIR");
+        assert_eq!(expand_path("cost$5", var), "cost$5");
+        assert_eq!(expand_path("a~b", var), "a~b");
+        assert_eq!(expand_path("~/notes.txt", var), "/home/me/notes.txt");
+        assert_eq!(expand_path("~", var), "/home/me");
+        assert_eq!(expand_path("~/notes.txt", |_| None), "~/notes.txt");
+    }
+
+    #[test]
+    fn restore_cursor_clamps_stale_position() {
+        let path = std::env::temp_dir().join("hammare_cursor_state.txt");
+        fs::write(&path, "one\ntwo\n").expect("Failed writing file");
+        let document = Document::open(path.to_str().expect("Failed converting path")).expect("Failed opening file");
+        let canonical = fs::canonicalize(&path).expect("Failed canonicalizing path");
+        assert_eq!(document.cursor_state_key(), Some(canonical.to_string_lossy().into_owned()));
+        assert_eq!(document.restore_cursor(&Position { x: 10, y: 40 }), Position { x: 3, y: 1 });
+        assert_eq!(document.restore_cursor(&Position { x: 10, y: 0 }), Position { x: 3, y: 0 });
+        assert_eq!(document.restore_cursor(&Position { x: 1, y: 1 }), Position { x: 1, y: 1 });
+        assert_eq!(Document::default().restore_cursor(&Position { x: 1, y: 1 }), Position::default());
+        assert_eq!(Document::default().cursor_state_key(), None);
+        fs::remove_file(&path).expect("Failed removing file");
+    }
+
+    #[test]
+    fn display_settings_do_not_change_saved_bytes() {
+        let path = std::env::temp_dir().join("hammare_tab_round_trip.txt");
+        let file_name = path.to_str().expect("Failed converting path").to_owned();
+        let contents = "fn main() {\n\tlet x = 1;\t// one\n    \tmixed\n}\n";
+        fs::write(&path, contents).expect("Failed writing file");
+        let mut document = Document::open(&file_name).expect("Failed opening file");
+        for tab_width in [2, 8, 4] {
+            document.set_tab_width(tab_width);
+            document.set_indent_style(IndentStyle::Spaces);
+            document.highlight(&None, None);
+            for y in 0..document.len() {
+                let row = document.row(y).expect("Failed getting row");
+                assert!(!row.render(0, usize::MAX, tab_width, ControlCharDisplay::default()).contains('\t'));
+            }
+        }
+        document.set_indent_style(IndentStyle::Tabs);
+        document.save().expect("Failed saving file");
+        assert_eq!(fs::read(&path).expect("Failed reading file"), contents.as_bytes());
+        fs::remove_file(&path).expect("Failed removing file");
+    }
+
+    #[test]
+    fn save_keeps_trailing_whitespace_of_untouched_rows() {
+        let path = std::env::temp_dir().join("hammare_whitespace_churn.txt");
+        let file_name = path.to_str().expect("Failed converting path").to_owned();
+        fs::write(&path, "one  \ntwo\t\nthree\nfour \n").expect("Failed writing file");
+        let mut document = Document::open(&file_name).expect("Failed opening file");
+        document.set_minimize_whitespace_churn(true);
+        document.delete(&Position { x: 4, y: 0 });
+        document.delete(&Position { x: 3, y: 1 });
+        document.insert(&Position { x: 5, y: 2 }, '!');
+        document.insert_str(&Position { x: 0, y: 2 }, "new \n");
+        document.delete(&Position { x: 4, y: 4 });
+        document.save().expect("Failed saving file");
+        assert_eq!(fs::read_to_string(&path).expect("Failed reading file"), "one  \ntwo\t\nnew \nthree!\nfour \n");
+        assert_eq!(document.row(0).map(Row::as_str), Some("one  "));
+
+        document.set_minimize_whitespace_churn(false);
+        document.delete(&Position { x: 4, y: 4 });
+        document.save().expect("Failed saving file");
+        assert_eq!(fs::read_to_string(&path).expect("Failed reading file"), "one  \ntwo\t\nnew \nthree!\nfour\n");
+        fs::remove_file(&path).expect("Failed removing file");
+    }
+
+    #[test]
+    fn save_incremental_on_disk() {
+        let path = std::env::temp_dir().join("hammare_save_incremental.txt");
+        let file_name = path.to_str().expect("Failed converting path").to_owned();
+        fs::write(&path, "one\ntwo\nthree\n").expect("Failed writing file");
+        let mut document = Document::open(&file_name).expect("Failed opening file");
+        document.delete(&Position { x: 0, y: 2 });
+        document.save_incremental().expect("Failed saving file");
+        assert_eq!(fs::read_to_string(&path).expect("Failed reading file"), "one\ntwo\nhree\n");
+        fs::remove_file(&path).expect("Failed removing file");
     }
 }
@@ -12,6 +12,7 @@ const STATUS_FG_COLOR: color::Rgb = color::Rgb(63, 63, 63);
 const STATUS_BG_COLOR: color::Rgb = color::Rgb(239, 239, 239);
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const QUIT_TIMES: u8 = 3;
+const SCROLLOFF: usize = 3;
 
 #[derive(PartialEq, Copy, Clone)]
 #[non_exhaustive]
@@ -20,13 +21,51 @@ pub enum SearchDirection {
     Backward,
 }
 
-#[derive(Default, Clone)]
+#[derive(PartialEq, Copy, Clone)]
+#[non_exhaustive]
+pub enum VerticalDirection {
+    Up,
+    Down,
+}
+
+#[derive(Default, Clone, PartialEq, Eq, Debug)]
 #[non_exhaustive]
 pub struct Position {
     pub x: usize,
     pub y: usize,
 }
 
+impl Position {
+    /// Returns `true` if `self` comes before `other` in the document
+    #[must_use]
+    pub fn is_before(&self, other: &Self) -> bool {
+        (self.y, self.x) < (other.y, other.x)
+    }
+}
+
+/// Returns the first of the sorted `positions` that comes after `at`, or the
+/// first one overall if `wrap` is set and there is none after `at`
+#[must_use]
+pub fn next_position_after(positions: &[Position], at: &Position, wrap: bool) -> Option<Position> {
+    positions
+        .iter()
+        .find(|position| at.is_before(position))
+        .or_else(|| if wrap { positions.first() } else { None })
+        .cloned()
+}
+
+/// Returns the last of the sorted `positions` that comes before `at`, or the
+/// last one overall if `wrap` is set and there is none before `at`
+#[must_use]
+pub fn prev_position_before(positions: &[Position], at: &Position, wrap: bool) -> Option<Position> {
+    positions
+        .iter()
+        .rev()
+        .find(|position| position.is_before(at))
+        .or_else(|| if wrap { positions.last() } else { None })
+        .cloned()
+}
+
 struct StatusMessage {
     text: String,
     time: Instant,
@@ -45,6 +84,7 @@ pub struct Editor {
     should_quit: bool,
     terminal: Terminal,
     cursor_position: Position,
+    /// The first row and the first display column shown on the screen
     offset: Position,
     document: Document,
     status_message: StatusMessage,
@@ -55,6 +95,7 @@ pub struct Editor {
 impl Editor {
     /// Runs the editor
     pub fn run(&mut self) {
+        self.scroll();
         loop {
             if let Err(error) = self.refresh_screen() {
                 die(&error);
@@ -75,12 +116,21 @@ impl Editor {
             "HELP: Ctrl-F = find \u{1f50d} | Ctrl-S = save \u{1f916} | Ctrl-T = quit \u{2620}\u{fe0f}",
         );
 
+        let mut cursor_position = Position::default();
         let document = if let Some(file_name) = args.get(1) {
-            let doc = Document::open(file_name);
+            let doc = Document::open_at(file_name)
+                .or_else(|_| Document::open_lossy_at(file_name))
+                .map(|(doc, position)| {
+                    cursor_position = position;
+                    doc
+                });
             if let Ok(doc) = doc {
+                if doc.is_lossy() {
+                    initial_status = format!("WARNING! {file_name} is not valid UTF-8, saving it will replace the invalid bytes.");
+                }
                 doc
             } else {
-                initial_status = format!("ERR: Could not open file: {}", file_name);
+                initial_status = format!("ERR: Could not open file: {file_name}");
                 Document::default()
             }
         } else {
@@ -90,7 +140,7 @@ impl Editor {
         Self {
             should_quit: false,
             terminal: Terminal::default().expect("Failed to initialize terminal"),
-            cursor_position: Position::default(),
+            cursor_position,
             offset: Position::default(),
             document,
             status_message: StatusMessage::from(initial_status),
@@ -108,14 +158,14 @@ impl Editor {
             println!("May the force be with you \u{26a1}\u{fe0f}\r");
         } else {
             self.document.highlight(&self.highlighted_word, Some(
-                self.offset.y.saturating_add(self.terminal.size().height.try_into().expect("Failed while trying to convert terminal size to usize")),
+                self.offset.y.saturating_add(self.terminal.size().height.into()),
             ),
         );
             self.draw_rows();
             self.draw_status_bar();
             self.draw_message_bar();
             Terminal::cursor_position(&Position {
-                x: self.cursor_position.x.saturating_sub(self.offset.x),
+                x: self.document.visual_column(&self.cursor_position).saturating_sub(self.offset.x),
                 y: self.cursor_position.y.saturating_sub(self.offset.y),
             });
         }
@@ -133,7 +183,7 @@ impl Editor {
             self.document.file_name = new_name;
         }
 
-        if self.document.save().is_ok() {
+        if self.document.save_incremental().is_ok() {
             self.status_message = StatusMessage::from("File saved successfully.".to_owned());
         } else {
             self.status_message = StatusMessage::from("Error writing file!".to_owned());
@@ -146,10 +196,20 @@ impl Editor {
         let mut direction = SearchDirection::Forward;
         let query = self
             .prompt(
-                "Search (ESC to cancel, Arrows to navigate): ",
+                "Search (ESC to cancel, Arrows to navigate, Ctrl-P/Ctrl-N for history): ",
                 |editor, key, query| {
                     let mut moved = false;
                     match key {
+                        Key::Ctrl('p') => {
+                            direction = SearchDirection::Forward;
+                            if let Some(previous) = editor.document.history_prev() {
+                                previous.clone_into(query);
+                            }
+                        }
+                        Key::Ctrl('n') => {
+                            direction = SearchDirection::Forward;
+                            editor.document.history_next().unwrap_or_default().clone_into(query);
+                        }
                         Key::Right | Key::Down => {
                             direction = SearchDirection::Forward;
                             editor.move_cursor(Key::Right);
@@ -158,22 +218,24 @@ impl Editor {
                         Key::Left | Key::Up => direction = SearchDirection::Backward,
                         _ => direction = SearchDirection::Forward,
                     }
-                    if let Some(position) =
+                    if let Some((position, _)) =
                         editor
                             .document
-                            .find(query, &editor.cursor_position, direction)
+                            .find_with_options(query, &editor.cursor_position, direction, true, false)
                     {
                         editor.cursor_position = position;
                         editor.scroll();
                     } else if moved {
                         editor.move_cursor(Key::Left);
                     }
-                    editor.highlighted_word = Some(query.to_string());
+                    editor.highlighted_word = Some(query.clone());
                 },
             )
             .unwrap_or(None);
 
-        if query.is_none() {
+        if let Some(query) = query.as_deref() {
+            self.document.push_search(query);
+        } else {
             self.cursor_position = old_position;
             self.scroll();
         }
@@ -181,43 +243,39 @@ impl Editor {
     }
 
     fn draw_status_bar(&self) {
-        let width: usize = self
-            .terminal
-            .size()
-            .width
-            .try_into()
-            .expect("Failed converting terminal size to usize");
-        let modified_indicator = if self.document.is_dirty() {
+        let width: usize = self.terminal.size().width.into();
+        let info = self.document.status_info(&self.cursor_position);
+        let modified_indicator = if info.is_dirty {
             " (modified)"
         } else {
             ""
         };
 
         let mut file_name = "[No Name]".to_owned();
-        if let Some(ref name) = self.document.file_name {
-            file_name = name.clone();
+        if let Some(name) = info.file_name.as_ref() {
+            file_name.clone_from(name);
             file_name.truncate(20);
         }
         let mut status = format!(
             "{} - {} lines{}",
             file_name,
-            self.document.len(),
+            info.lines,
             modified_indicator
         );
         let line_indicator = format!(
             "{} | {}/{}",
-            self.document.file_type(),
-            self.cursor_position.y.saturating_add(1),
-            self.document.len()
+            info.file_type,
+            info.line,
+            info.lines
         );
-        #[allow(clippy::integer_arithmetic)]
+        #[allow(clippy::arithmetic_side_effects)]
         let len = status.len() + line_indicator.len();
         status.push_str(&" ".repeat(width.saturating_sub(len)));
-        status = format!("{}{}", status, line_indicator);
+        status = format!("{status}{line_indicator}");
         status.truncate(width);
         Terminal::set_bg_color(STATUS_BG_COLOR);
         Terminal::set_fg_color(STATUS_FG_COLOR);
-        println!("{}\r", status);
+        println!("{status}\r");
         Terminal::reset_fg_color();
         Terminal::reset_bg_color();
     }
@@ -225,16 +283,10 @@ impl Editor {
     fn draw_message_bar(&self) {
         Terminal::clear_current_line();
         let message = &self.status_message;
-        if Instant::now() - message.time < Duration::new(5, 0) {
+        if message.time.elapsed() < Duration::new(5, 0) {
             let mut text = message.text.clone();
-            text.truncate(
-                self.terminal
-                    .size()
-                    .width
-                    .try_into()
-                    .expect("Failed converting terminal size to usize"),
-            );
-            print!("{}", text);
+            text.truncate(self.terminal.size().width.into());
+            print!("{text}");
         }
     }
 
@@ -255,17 +307,64 @@ impl Editor {
             }
             Key::Ctrl('s') => self.save(),
             Key::Ctrl('f') => self.search(),
+            Key::Ctrl('k') => {
+                self.document.kill_line(&self.cursor_position);
+            }
+            Key::Alt('d') => {
+                self.document.delete_word(&self.cursor_position);
+            }
+            Key::Ctrl('y') => {
+                if let Some(position) = self.document.yank(&self.cursor_position) {
+                    self.cursor_position = position;
+                }
+            }
+            Key::Alt('y') => {
+                if let Some(position) = self.document.yank_pop() {
+                    self.cursor_position = position;
+                }
+            }
+            Key::Ctrl('z') => {
+                if let Some(position) = self.document.undo() {
+                    self.cursor_position = position;
+                }
+            }
+            Key::Alt('z') => {
+                if let Some(position) = self.document.redo() {
+                    self.cursor_position = position;
+                }
+            }
+            Key::Alt('{') => {
+                self.cursor_position = self.document.next_paragraph(&self.cursor_position, VerticalDirection::Up);
+            }
+            Key::Alt('}') => {
+                self.cursor_position = self.document.next_paragraph(&self.cursor_position, VerticalDirection::Down);
+            }
+            Key::Char('\t') => {
+                self.cursor_position = self.document.insert_indent(&self.cursor_position);
+            }
+            Key::BackTab => {
+                self.cursor_position = self.document.remove_indent(&self.cursor_position);
+            }
+            Key::Char('\n') => {
+                self.cursor_position = self.document.split_line(&self.cursor_position);
+            }
             Key::Char(c) => {
-                self.document.insert(&self.cursor_position, c);
-                self.move_cursor(Key::Right);
+                self.cursor_position = self.document.type_char(&self.cursor_position, c);
             }
-            Key::Delete => self.document.delete(&self.cursor_position),
-            Key::Backspace => {
-                if self.cursor_position.x > 0 || self.cursor_position.y > 0 {
-                    self.move_cursor(Key::Left);
+            Key::Insert => {
+                let overwrite = !self.document.is_overwrite();
+                self.document.set_overwrite(overwrite);
+            }
+            Key::Delete => {
+                if let Some(position) = self.document.delete_selection() {
+                    self.cursor_position = position;
+                } else {
                     self.document.delete(&self.cursor_position);
                 }
             }
+            Key::Backspace => {
+                self.cursor_position = self.document.backspace(&self.cursor_position);
+            }
             Key::Up
             | Key::Down
             | Key::Left
@@ -285,26 +384,12 @@ impl Editor {
     }
 
     fn scroll(&mut self) {
-        let Position { x, y } = self.cursor_position;
-        let width = self
-            .terminal
-            .size()
-            .width
-            .try_into()
-            .expect("Failed converting terminal size to usize");
-        let height = self
-            .terminal
-            .size()
-            .height
-            .try_into()
-            .expect("Failed converting terminal size to usize");
-        let mut offset = &mut self.offset;
-
-        if y < offset.y {
-            offset.y = y;
-        } else if y >= offset.y.saturating_add(height) {
-            offset.y = y.saturating_sub(height).saturating_add(1);
-        }
+        let x = self.document.visual_column(&self.cursor_position);
+        let y = self.cursor_position.y;
+        let width = self.terminal.size().width.into();
+        let height = self.terminal.size().height.into();
+        self.offset.y = self.document.scroll_offset(y, self.offset.y, height, SCROLLOFF);
+        let offset = &mut self.offset;
 
         if x < offset.x {
             offset.x = x;
@@ -314,14 +399,9 @@ impl Editor {
     }
 
     fn move_cursor(&mut self, key: Key) {
-        let terminal_height = self
-            .terminal
-            .size()
-            .height
-            .try_into()
-            .expect("Failed converting terminal size to usize");
+        let terminal_height = self.terminal.size().height.into();
         let Position { mut x, mut y } = self.cursor_position;
-        let height = self.document.len();
+        let last_line = self.document.line_count_with_virtual().saturating_sub(1);
         let mut width = if let Some(row) = self.document.row(y) {
             row.len()
         } else {
@@ -329,11 +409,7 @@ impl Editor {
         };
         match key {
             Key::Up => y = y.saturating_sub(1),
-            Key::Down => {
-                if y < height {
-                    y = y.saturating_add(1);
-                }
-            }
+            Key::Down if y < last_line => y = y.saturating_add(1),
             Key::Left => {
                 if x > 0 {
                     x = x.saturating_sub(1);
@@ -349,7 +425,7 @@ impl Editor {
             Key::Right => {
                 if x < width {
                     x = x.saturating_add(1);
-                } else if y < height {
+                } else if y < last_line {
                     y = y.saturating_add(1);
                     x = 0;
                 }
@@ -362,10 +438,10 @@ impl Editor {
                 }
             }
             Key::PageDown => {
-                y = if y.saturating_add(terminal_height) < height {
+                y = if y.saturating_add(terminal_height) < last_line {
                     y.saturating_add(terminal_height)
                 } else {
-                    height
+                    last_line
                 }
             }
             Key::Home => x = 0,
@@ -386,49 +462,38 @@ impl Editor {
     fn draw_welcome_message(&self) {
         let mut welcome_message =
             format!("Welcome to Hammare Editor \u{1f528} -- version {VERSION}");
-        let width: usize = self
-            .terminal
-            .size()
-            .width
-            .try_into()
-            .expect("Failed converting terminal size to usize");
+        let width: usize = self.terminal.size().width.into();
         let len = welcome_message.len();
 
+        #[allow(clippy::integer_division_remainder_used)]
         let padding = width.saturating_sub(len) / 2;
 
         let spaces = "  ".repeat(padding.saturating_sub(1));
         welcome_message = format!("~{spaces}{welcome_message}");
         welcome_message.truncate(width);
-        println!("{}\r", welcome_message);
+        println!("{welcome_message}\r");
     }
 
     /// Draw a single row based on the cursor position
     pub fn draw_row(&self, row: &Row) {
-        let width = self
-            .terminal
-            .size()
-            .width
-            .try_into()
-            .expect("Failed converting terminal size to usize");
+        let width = self.terminal.size().width.into();
         let start = self.offset.x;
         let end = self.offset.x.saturating_add(width);
-        let row = row.render(start, end);
-        println!("{}\r", row);
+        let row = row.render(start, end, self.document.tab_width(), self.document.control_char_display());
+        println!("{row}\r");
     }
 
     fn draw_rows(&self) {
         let height = self.terminal.size().height;
+        #[allow(clippy::integer_division_remainder_used)]
+        let welcome_row = height / 3;
         for terminal_row in 0..height {
             Terminal::clear_current_line();
             if let Some(row) = self.document.row(
-                self.offset.y.saturating_add(
-                    terminal_row
-                        .try_into()
-                        .expect("Failed trying to convert terminal_row into usize"),
-                ),
+                self.offset.y.saturating_add(terminal_row.into()),
             ) {
                 self.draw_row(row);
-            } else if self.document.is_empty() && terminal_row == height / 3 {
+            } else if self.document.is_empty() && terminal_row == welcome_row {
                 self.draw_welcome_message();
             } else {
                 println!("~\r");
@@ -438,11 +503,11 @@ impl Editor {
 
     fn prompt<C>(&mut self, prompt: &str, mut callback: C) -> Result<Option<String>, std::io::Error>
     where
-        C: FnMut(&mut Self, Key, &String),
+        C: FnMut(&mut Self, Key, &mut String),
     {
         let mut result = String::new();
         loop {
-            self.status_message = StatusMessage::from(format!("{}{}", prompt, result));
+            self.status_message = StatusMessage::from(format!("{prompt}{result}"));
             self.refresh_screen()?;
 
             let key = Terminal::read_key()?;
@@ -450,18 +515,14 @@ impl Editor {
             match key {
                 Key::Backspace => result.truncate(result.len().saturating_sub(1)),
                 Key::Char('\n') => break,
-                Key::Char(c) => {
-                    if !c.is_control() {
-                        result.push(c);
-                    }
-                }
+                Key::Char(c) if !c.is_control() => result.push(c),
                 Key::Esc => {
                     result.truncate(0);
                     break;
                 }
                 _ => (),
             }
-            callback(self, key, &result);
+            callback(self, key, &mut result);
         }
         self.status_message = StatusMessage::from(String::new());
         if result.is_empty() {
@@ -471,9 +532,42 @@ impl Editor {
     }
 }
 
+#[allow(clippy::print_stderr)]
 fn die(e: &std::io::Error) {
     Terminal::clear_screen();
     eprintln!("Unexpected behavior while quitting the program. {e}");
     #[allow(clippy::exit)]
     process::exit(1)
 }
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    #[test]
+    fn next_position_after_wraps() {
+        let markers = vec![
+            Position { x: 0, y: 1 },
+            Position { x: 0, y: 3 },
+            Position { x: 0, y: 5 },
+        ];
+        let next = next_position_after(&markers, &Position { x: 0, y: 3 }, true);
+        assert_eq!(next, Some(Position { x: 0, y: 5 }));
+        let wrapped = next_position_after(&markers, &Position { x: 0, y: 5 }, true);
+        assert_eq!(wrapped, Some(Position { x: 0, y: 1 }));
+        assert_eq!(next_position_after(&markers, &Position { x: 0, y: 5 }, false), None);
+    }
+
+    #[test]
+    fn prev_position_before_wraps() {
+        let markers = vec![
+            Position { x: 0, y: 1 },
+            Position { x: 0, y: 3 },
+            Position { x: 0, y: 5 },
+        ];
+        let prev = prev_position_before(&markers, &Position { x: 0, y: 3 }, true);
+        assert_eq!(prev, Some(Position { x: 0, y: 1 }));
+        let wrapped = prev_position_before(&markers, &Position { x: 0, y: 1 }, true);
+        assert_eq!(wrapped, Some(Position { x: 0, y: 5 }));
+    }
+}
@@ -1,9 +1,11 @@
+#[derive(Clone)]
 pub struct FileType {
     name: String,
     hl_opts: HighlightingOptions,
+    block_comment: Option<(&'static str, &'static str)>,
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 #[allow(clippy::struct_excessive_bools)]
 pub struct HighlightingOptions {
     numbers: bool,
@@ -11,16 +13,26 @@ pub struct HighlightingOptions {
     characters: bool,
     comments: bool,
     multiline_comments: bool,
+    trailing_whitespace: bool,
+    punctuation: bool,
+    brackets: bool,
+    markdown: bool,
     primary_keywords: Vec<String>,
-    secondary_keywords: Vec<String>
+    secondary_keywords: Vec<String>,
+    annotations: Vec<String>,
+    line_comment: Option<String>,
+}
+
+/// Builds `HighlightingOptions` for a language of its own, see
+/// `HighlightingOptions::builder`
+#[derive(Default)]
+pub struct HighlightingOptionsBuilder {
+    options: HighlightingOptions,
 }
 
 impl Default for FileType {
     fn default() -> Self {
-        Self {
-            name: String::from("No filetype"),
-            hl_opts: HighlightingOptions::default(),
-        }
+        Self::plain()
     }
 }
 
@@ -35,100 +47,406 @@ impl FileType {
     pub fn highlighting_options(&self) -> &HighlightingOptions {
         &self.hl_opts
     }
+    /// Returns the delimiters that open and close a block comment, if the
+    /// language has block comments
+    #[must_use]
+    pub fn block_comment(&self) -> Option<(&str, &str)> {
+        self.block_comment
+    }
+    /// Returns the marker that starts a comment running to the end of the
+    /// row, if the language has one
+    #[must_use]
+    pub fn line_comment(&self) -> Option<&str> {
+        self.hl_opts.line_comment()
+    }
+
+    /// Sets the words, such as `TODO`, highlighted as annotations in the
+    /// comments of the language
+    pub fn set_annotations(&mut self, annotations: &[&str]) {
+        self.hl_opts.annotations = annotations.iter().map(|&annotation| annotation.to_owned()).collect();
+    }
+
+    /// A file type of its own named `name`, e.g. for a language the editor
+    /// does not know, highlighted with `hl_opts`
+    #[must_use]
+    pub fn new(name: &str, hl_opts: HighlightingOptions) -> Self {
+        Self {
+            name: name.to_owned(),
+            hl_opts,
+            block_comment: None,
+        }
+    }
+
+    /// Checks if the file type is prose, plain text or Markdown, rather than
+    /// code, e.g. for what is safe to hard-wrap
+    #[must_use]
+    pub fn is_prose(&self) -> bool {
+        self.hl_opts.markdown || self.name == "Plain Text"
+    }
+
+    /// The file type of files that are not recognized, named "Plain Text",
+    /// with all highlighting disabled
+    #[must_use]
+    pub fn plain() -> Self {
+        Self {
+            name: String::from("Plain Text"),
+            hl_opts: HighlightingOptions::default(),
+            block_comment: None,
+        }
+    }
+
     /// Gets the documents extenstion type
     #[must_use]
     pub fn from(file_name: &str) -> Self {
-        if file_name
-            .rsplit('.')
-            .next()
-            .map(|ext| ext.eq_ignore_ascii_case("rs"))
-            == Some(true)
-        {
-            return Self {
-                name: String::from("Rust"),
-                hl_opts: HighlightingOptions {
-                    numbers: true,
-                    strings: true,
-                    characters: true,
-                    comments: true,
-                    multiline_comments: true,
-                    primary_keywords: vec![
-                        "as".to_owned(),            
-                        "break".to_owned(),            
-                        "const".to_owned(),            
-                        "continue".to_owned(),            
-                        "crate".to_owned(),            
-                        "else".to_owned(),            
-                        "enum".to_owned(),            
-                        "extern".to_owned(),            
-                        "false".to_owned(),            
-                        "fn".to_owned(),            
-                        "for".to_owned(),            
-                        "if".to_owned(),            
-                        "impl".to_owned(),            
-                        "in".to_owned(),            
-                        "let".to_owned(),            
-                        "loop".to_owned(),            
-                        "match".to_owned(),            
-                        "mod".to_owned(),            
-                        "move".to_owned(),            
-                        "mut".to_owned(),            
-                        "pub".to_owned(),            
-                        "ref".to_owned(),            
-                        "return".to_owned(),            
-                        "self".to_owned(),            
-                        "Self".to_owned(),            
-                        "static".to_owned(),            
-                        "struct".to_owned(),            
-                        "super".to_owned(),            
-                        "trait".to_owned(),            
-                        "true".to_owned(),            
-                        "type".to_owned(),            
-                        "unsafe".to_owned(),            
-                        "use".to_owned(),            
-                        "where".to_owned(),            
-                        "while".to_owned(),            
-                        "dyn".to_owned(),            
-                        "abstract".to_owned(),            
-                        "become".to_owned(),            
-                        "box".to_owned(),            
-                        "do".to_owned(),            
-                        "final".to_owned(),            
-                        "macro".to_owned(),            
-                        "override".to_owned(),            
-                        "priv".to_owned(),            
-                        "typeof".to_owned(),            
-                        "unsized".to_owned(),            
-                        "virtual".to_owned(),            
-                        "yield".to_owned(),            
-                        "async".to_owned(),            
-                        "await".to_owned(),            
-                        "try".to_owned(),
-                    ],
-                    secondary_keywords: vec![
-                        "bool".to_owned(),            
-                        "char".to_owned(),            
-                        "i8".to_owned(),            
-                        "i16".to_owned(),            
-                        "i32".to_owned(),            
-                        "i64".to_owned(),            
-                        "isize".to_owned(),            
-                        "u8".to_owned(),            
-                        "u16".to_owned(),            
-                        "u32".to_owned(),            
-                        "u64".to_owned(),            
-                        "usize".to_owned(),            
-                        "f32".to_owned(),            
-                        "f64".to_owned(),  
-                    ]
-                },
-            };
+        Self::detect(file_name, None)
+    }
+
+    /// Gets the documents type from its extension, falling back to the
+    /// interpreter named in the shebang of `first_line` when the extension
+    /// is missing or unknown
+    #[must_use]
+    pub fn detect(file_name: &str, first_line: Option<&str>) -> Self {
+        if let Some(ext) = file_name.rsplit('.').next() {
+            match ext.to_ascii_lowercase().as_str() {
+                "rs" => return Self::rust(),
+                "c" | "h" => return Self::c(),
+                "py" => return Self::python(),
+                "sh" | "bash" => return Self::shell(),
+                "json" => return Self::json(),
+                "md" | "markdown" => return Self::markdown(),
+                _ => (),
+            }
+        }
+        match first_line.and_then(interpreter) {
+            Some(name) if name.starts_with("python") => Self::python(),
+            Some("sh" | "bash" | "zsh" | "dash" | "ksh") => Self::shell(),
+            _ => Self::plain(),
+        }
+    }
+
+    fn rust() -> Self {
+        Self {
+            name: String::from("Rust"),
+            hl_opts: HighlightingOptions {
+                numbers: true,
+                strings: true,
+                characters: true,
+                comments: true,
+                multiline_comments: true,
+                trailing_whitespace: true,
+                punctuation: false,
+                brackets: false,
+                markdown: false,
+                primary_keywords: vec![
+                    "as".to_owned(),            
+                    "break".to_owned(),            
+                    "const".to_owned(),            
+                    "continue".to_owned(),            
+                    "crate".to_owned(),            
+                    "else".to_owned(),            
+                    "enum".to_owned(),            
+                    "extern".to_owned(),            
+                    "false".to_owned(),            
+                    "fn".to_owned(),            
+                    "for".to_owned(),            
+                    "if".to_owned(),            
+                    "impl".to_owned(),            
+                    "in".to_owned(),            
+                    "let".to_owned(),            
+                    "loop".to_owned(),            
+                    "match".to_owned(),            
+                    "mod".to_owned(),            
+                    "move".to_owned(),            
+                    "mut".to_owned(),            
+                    "pub".to_owned(),            
+                    "ref".to_owned(),            
+                    "return".to_owned(),            
+                    "self".to_owned(),            
+                    "Self".to_owned(),            
+                    "static".to_owned(),            
+                    "struct".to_owned(),            
+                    "super".to_owned(),            
+                    "trait".to_owned(),            
+                    "true".to_owned(),            
+                    "type".to_owned(),            
+                    "unsafe".to_owned(),            
+                    "use".to_owned(),            
+                    "where".to_owned(),            
+                    "while".to_owned(),            
+                    "dyn".to_owned(),            
+                    "abstract".to_owned(),            
+                    "become".to_owned(),            
+                    "box".to_owned(),            
+                    "do".to_owned(),            
+                    "final".to_owned(),            
+                    "macro".to_owned(),            
+                    "override".to_owned(),            
+                    "priv".to_owned(),            
+                    "typeof".to_owned(),            
+                    "unsized".to_owned(),            
+                    "virtual".to_owned(),            
+                    "yield".to_owned(),            
+                    "async".to_owned(),            
+                    "await".to_owned(),            
+                    "try".to_owned(),
+                ],
+                secondary_keywords: vec![
+                    "bool".to_owned(),            
+                    "char".to_owned(),            
+                    "i8".to_owned(),            
+                    "i16".to_owned(),            
+                    "i32".to_owned(),            
+                    "i64".to_owned(),            
+                    "isize".to_owned(),            
+                    "u8".to_owned(),            
+                    "u16".to_owned(),            
+                    "u32".to_owned(),            
+                    "u64".to_owned(),            
+                    "usize".to_owned(),            
+                    "f32".to_owned(),            
+                    "f64".to_owned(),  
+                ],
+                annotations: default_annotations(),
+                line_comment: Some("//".to_owned()),
+            },
+            block_comment: Some(("/*", "*/")),
+        }
+    }
+
+    fn c() -> Self {
+        Self {
+            name: String::from("C"),
+            hl_opts: HighlightingOptions {
+                numbers: true,
+                strings: true,
+                characters: true,
+                comments: true,
+                multiline_comments: true,
+                trailing_whitespace: true,
+                punctuation: false,
+                brackets: false,
+                markdown: false,
+                primary_keywords: vec![
+                    "break".to_owned(),
+                    "case".to_owned(),
+                    "const".to_owned(),
+                    "continue".to_owned(),
+                    "default".to_owned(),
+                    "do".to_owned(),
+                    "else".to_owned(),
+                    "enum".to_owned(),
+                    "extern".to_owned(),
+                    "for".to_owned(),
+                    "goto".to_owned(),
+                    "if".to_owned(),
+                    "return".to_owned(),
+                    "sizeof".to_owned(),
+                    "static".to_owned(),
+                    "struct".to_owned(),
+                    "switch".to_owned(),
+                    "typedef".to_owned(),
+                    "union".to_owned(),
+                    "volatile".to_owned(),
+                    "while".to_owned(),
+                ],
+                secondary_keywords: vec![
+                    "char".to_owned(),
+                    "double".to_owned(),
+                    "float".to_owned(),
+                    "int".to_owned(),
+                    "long".to_owned(),
+                    "short".to_owned(),
+                    "signed".to_owned(),
+                    "unsigned".to_owned(),
+                    "void".to_owned(),
+                ],
+                annotations: default_annotations(),
+                line_comment: Some("//".to_owned()),
+            },
+            block_comment: Some(("/*", "*/")),
+        }
+    }
+
+    fn python() -> Self {
+        Self {
+            name: String::from("Python"),
+            hl_opts: HighlightingOptions {
+                numbers: true,
+                strings: true,
+                characters: false,
+                comments: true,
+                multiline_comments: false,
+                trailing_whitespace: true,
+                punctuation: false,
+                brackets: false,
+                markdown: false,
+                primary_keywords: vec![
+                    "and".to_owned(),
+                    "as".to_owned(),
+                    "assert".to_owned(),
+                    "async".to_owned(),
+                    "await".to_owned(),
+                    "break".to_owned(),
+                    "class".to_owned(),
+                    "continue".to_owned(),
+                    "def".to_owned(),
+                    "del".to_owned(),
+                    "elif".to_owned(),
+                    "else".to_owned(),
+                    "except".to_owned(),
+                    "False".to_owned(),
+                    "finally".to_owned(),
+                    "for".to_owned(),
+                    "from".to_owned(),
+                    "global".to_owned(),
+                    "if".to_owned(),
+                    "import".to_owned(),
+                    "in".to_owned(),
+                    "is".to_owned(),
+                    "lambda".to_owned(),
+                    "None".to_owned(),
+                    "nonlocal".to_owned(),
+                    "not".to_owned(),
+                    "or".to_owned(),
+                    "pass".to_owned(),
+                    "raise".to_owned(),
+                    "return".to_owned(),
+                    "self".to_owned(),
+                    "True".to_owned(),
+                    "try".to_owned(),
+                    "while".to_owned(),
+                    "with".to_owned(),
+                    "yield".to_owned(),
+                ],
+                secondary_keywords: vec![
+                    "bool".to_owned(),
+                    "bytes".to_owned(),
+                    "dict".to_owned(),
+                    "float".to_owned(),
+                    "int".to_owned(),
+                    "list".to_owned(),
+                    "set".to_owned(),
+                    "str".to_owned(),
+                    "tuple".to_owned(),
+                ],
+                annotations: default_annotations(),
+                line_comment: Some("#".to_owned()),
+            },
+            block_comment: None,
+        }
+    }
+
+    fn shell() -> Self {
+        Self {
+            name: String::from("Shell"),
+            hl_opts: HighlightingOptions {
+                numbers: true,
+                strings: true,
+                characters: false,
+                comments: true,
+                multiline_comments: false,
+                trailing_whitespace: true,
+                punctuation: false,
+                brackets: false,
+                markdown: false,
+                primary_keywords: vec![
+                    "case".to_owned(),
+                    "do".to_owned(),
+                    "done".to_owned(),
+                    "elif".to_owned(),
+                    "else".to_owned(),
+                    "esac".to_owned(),
+                    "fi".to_owned(),
+                    "for".to_owned(),
+                    "function".to_owned(),
+                    "if".to_owned(),
+                    "in".to_owned(),
+                    "return".to_owned(),
+                    "then".to_owned(),
+                    "until".to_owned(),
+                    "while".to_owned(),
+                ],
+                secondary_keywords: vec![
+                    "echo".to_owned(),
+                    "exit".to_owned(),
+                    "export".to_owned(),
+                    "local".to_owned(),
+                    "read".to_owned(),
+                    "set".to_owned(),
+                    "shift".to_owned(),
+                    "source".to_owned(),
+                    "unset".to_owned(),
+                ],
+                annotations: default_annotations(),
+                line_comment: Some("#".to_owned()),
+            },
+            block_comment: None,
+        }
+    }
+
+    fn markdown() -> Self {
+        Self {
+            name: String::from("Markdown"),
+            hl_opts: HighlightingOptions {
+                markdown: true,
+                numbers: false,
+                ..HighlightingOptions::default()
+            },
+            block_comment: Some(("<!--", "-->")),
+        }
+    }
+
+    fn json() -> Self {
+        Self {
+            name: String::from("JSON"),
+            hl_opts: HighlightingOptions {
+                numbers: true,
+                strings: true,
+                characters: false,
+                comments: false,
+                multiline_comments: false,
+                trailing_whitespace: true,
+                punctuation: true,
+                brackets: true,
+                markdown: false,
+                primary_keywords: vec![
+                    "true".to_owned(),
+                    "false".to_owned(),
+                    "null".to_owned(),
+                ],
+                secondary_keywords: Vec::new(),
+                annotations: Vec::new(),
+                line_comment: None,
+            },
+            block_comment: None,
         }
-        Self::default()
     }
 }
 
+/// Returns the words highlighted as annotations in the comments of code
+fn default_annotations() -> Vec<String> {
+    vec!["TODO".to_owned(), "FIXME".to_owned(), "HACK".to_owned(), "XXX".to_owned()]
+}
+
+/// Returns the name of the interpreter of a shebang line, looking through
+/// `env` so that `#!/usr/bin/env python3` gives `python3`
+fn interpreter(line: &str) -> Option<&str> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        return words.find(|word| !word.starts_with('-'));
+    }
+    Some(program)
+}
+
 impl HighlightingOptions {
+    /// Starts building options with all highlighting disabled, e.g.
+    /// `HighlightingOptions::builder().keywords(&["fn"]).line_comment("//").build()`
+    #[must_use]
+    pub fn builder() -> HighlightingOptionsBuilder {
+        HighlightingOptionsBuilder::default()
+    }
+
     /// Return a boolean indicating if we should highlight numbers or not
     #[must_use]
     pub fn numbers(&self) -> bool {
@@ -167,10 +485,124 @@ impl HighlightingOptions {
         &self.secondary_keywords
     }
 
+    /// Return the words, such as `TODO`, highlighted as annotations when they
+    /// appear in a comment
+    #[must_use]
+    pub fn annotations(&self) -> &Vec<String> {
+        &self.annotations
+    }
+
+    /// Return the marker that starts a comment running to the end of the
+    /// row, if the language has one
+    #[must_use]
+    pub fn line_comment(&self) -> Option<&str> {
+        self.line_comment.as_deref()
+    }
+
     /// Return a boolean indicating if we should highlight multiline comments
     #[must_use]
     pub fn multiline_comments(&self) -> bool {
         self.multiline_comments
     }
 
+    /// Return a boolean indicating if we should highlight `:` and `,`
+    #[must_use]
+    pub fn punctuation(&self) -> bool {
+        self.punctuation
+    }
+
+    /// Return a boolean indicating if we should color brackets and braces by
+    /// their nesting depth
+    #[must_use]
+    pub fn brackets(&self) -> bool {
+        self.brackets
+    }
+
+    /// Return a boolean indicating if rows should be highlighted as Markdown
+    /// instead of code
+    #[must_use]
+    pub fn markdown(&self) -> bool {
+        self.markdown
+    }
+
+    /// Return a boolean indicating if we should highlight the spaces and tabs
+    /// at the end of a row
+    #[must_use]
+    pub fn highlight_trailing_whitespace(&self) -> bool {
+        self.trailing_whitespace
+    }
+
+}
+
+impl HighlightingOptionsBuilder {
+    /// Sets the words highlighted as primary keywords
+    #[must_use]
+    pub fn keywords(mut self, keywords: &[&str]) -> Self {
+        self.options.primary_keywords = keywords.iter().map(|&keyword| keyword.to_owned()).collect();
+        self
+    }
+
+    /// Sets the words highlighted as secondary keywords, such as types
+    #[must_use]
+    pub fn secondary_keywords(mut self, keywords: &[&str]) -> Self {
+        self.options.secondary_keywords = keywords.iter().map(|&keyword| keyword.to_owned()).collect();
+        self
+    }
+
+    /// Sets the marker of comments running to the end of the row, and turns
+    /// their highlighting on
+    #[must_use]
+    pub fn line_comment(mut self, marker: &str) -> Self {
+        self.options.line_comment = Some(marker.to_owned());
+        self.options.comments = true;
+        self
+    }
+
+    /// Sets whether `/* */` comments are highlighted
+    #[must_use]
+    pub fn multiline_comments(mut self, multiline_comments: bool) -> Self {
+        self.options.multiline_comments = multiline_comments;
+        self
+    }
+
+    /// Sets whether `"` strings are highlighted
+    #[must_use]
+    pub fn strings(mut self, strings: bool) -> Self {
+        self.options.strings = strings;
+        self
+    }
+
+    /// Sets whether `'` characters are highlighted
+    #[must_use]
+    pub fn characters(mut self, characters: bool) -> Self {
+        self.options.characters = characters;
+        self
+    }
+
+    /// Sets whether numbers are highlighted
+    #[must_use]
+    pub fn numbers(mut self, numbers: bool) -> Self {
+        self.options.numbers = numbers;
+        self
+    }
+
+    /// Sets whether the spaces and tabs at the end of a row are highlighted
+    #[must_use]
+    pub fn trailing_whitespace(mut self, trailing_whitespace: bool) -> Self {
+        self.options.trailing_whitespace = trailing_whitespace;
+        self
+    }
+
+    /// Sets the words, such as `TODO`, highlighted in comments
+    #[must_use]
+    pub fn annotations(mut self, annotations: &[&str]) -> Self {
+        self.options.annotations = annotations.iter().map(|&annotation| annotation.to_owned()).collect();
+        self
+    }
+
+    /// Returns the options built
+    #[must_use]
+    pub fn build(self) -> HighlightingOptions {
+        self.options
+    }
 }
@@ -1,6 +1,6 @@
 use termion::color;
 
-#[derive(PartialEq, Clone, Copy, Debug)]
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub enum Type {
     None,
     Number,
@@ -10,8 +10,29 @@ pub enum Type {
     Comment,
     MultilineComment,
     PrimaryKeywords,
-    SecondaryKeywords
+    SecondaryKeywords,
+    TrailingWhitespace,
+    Punctuation,
+    /// A bracket or brace, with its nesting depth
+    Bracket(usize),
+    Heading,
+    Emphasis,
+    Code,
+    /// A word such as `TODO` in a comment
+    Annotation,
+    /// The bracket under the cursor and the one matching it
+    MatchingBracket,
 }
+/// The highlighting state at the boundary between two rows
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+#[non_exhaustive]
+pub enum HlState {
+    #[default]
+    Normal,
+    InBlockComment,
+    InCodeFence,
+}
+
 impl Type {
     pub fn to_color(self) -> impl color::Color {
         match self {
@@ -20,8 +41,17 @@ impl Type {
             Type::String => color::Rgb(241, 250, 140),
             Type::Character => color::Rgb(108, 113, 196),
             Type::Comment | Type::MultilineComment => color::Rgb(98, 114, 164),
-            Type::PrimaryKeywords => color::Rgb(255, 121, 198),
+            Type::PrimaryKeywords | Type::Heading => color::Rgb(255, 121, 198),
             Type::SecondaryKeywords => color::Rgb(139, 233, 253),
+            Type::TrailingWhitespace => color::Rgb(255, 85, 85),
+            Type::Punctuation => color::Rgb(248, 248, 242),
+            Type::Bracket(depth) => match depth.checked_rem(3) {
+                Some(1) => color::Rgb(80, 250, 123),
+                Some(2) => color::Rgb(139, 233, 253),
+                _ => color::Rgb(255, 184, 108),
+            },
+            Type::Emphasis | Type::Annotation => color::Rgb(255, 184, 108),
+            Type::Code | Type::MatchingBracket => color::Rgb(80, 250, 123),
             Type::None => color::Rgb(255, 255, 255),
         }
     }
@@ -9,22 +9,52 @@
     clippy::expect_used,
     clippy::integer_division,
     clippy::blanket_clippy_restriction_lints,
-    clippy::too_many_lines
+    clippy::too_many_lines,
+    clippy::absolute_paths,
+    clippy::allow_attributes,
+    clippy::allow_attributes_without_reason,
+    clippy::arbitrary_source_item_ordering,
+    clippy::doc_paragraphs_missing_punctuation,
+    clippy::min_ident_chars,
+    clippy::missing_inline_in_public_items,
+    clippy::missing_trait_methods,
+    clippy::pub_use,
+    clippy::question_mark_used,
+    clippy::single_call_fn,
+    clippy::std_instead_of_alloc,
+    clippy::std_instead_of_core,
+    clippy::unused_trait_names
 )]
 mod document;
 mod editor;
 mod filetype;
 mod highlighting;
+mod quick_open;
 mod row;
 mod terminal;
 
+pub use document::save_all;
 pub use document::Document;
+pub use document::DocumentSnapshot;
+pub use document::IndentStyle;
+pub use document::LineEnding;
+pub use document::Lint;
+pub use document::SearchResult;
+pub use document::StatusInfo;
 use editor::Editor;
+pub use editor::next_position_after;
+pub use editor::prev_position_before;
 pub use editor::Position;
 pub use editor::SearchDirection;
+pub use editor::VerticalDirection;
 pub use filetype::FileType;
 pub use filetype::HighlightingOptions;
+pub use filetype::HighlightingOptionsBuilder;
+pub use quick_open::fuzzy_score;
+pub use quick_open::list_files;
+pub use row::ControlCharDisplay;
 pub use row::Row;
+pub use row::WordCharSet;
 pub use terminal::Terminal;
 
 fn main() {
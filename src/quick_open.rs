@@ -0,0 +1,117 @@
+use std::fs;
+use std::io::Error;
+use std::path::Path;
+
+const MAX_GAP_PENALTY: usize = 3;
+
+/// Lists the files under `root`, recursively, as paths relative to it
+/// separated by `/`, e.g. for a fuzzy file opener. `.git` directories are
+/// always skipped, other hidden files and directories only if
+/// `ignore_hidden` is set. Symbolic links are skipped so that a link to a
+/// parent directory can not make the walk loop.
+///
+/// # Errors
+/// It will return `Err` if `root` or one of its subdirectories can not be
+/// read
+pub fn list_files(root: &str, ignore_hidden: bool) -> Result<Vec<String>, Error> {
+    let mut files = Vec::new();
+    walk(Path::new(root), "", ignore_hidden, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+/// Scores how well `query` matches `candidate`, ignoring case, or returns
+/// `None` if the characters of `query` do not all appear in `candidate` in
+/// order. Matches that follow each other or start a path component, a word
+/// or a camelCase hump score higher; characters skipped between matches
+/// lower the score, up to `MAX_GAP_PENALTY` per gap.
+#[must_use]
+pub fn fuzzy_score(candidate: &str, query: &str) -> Option<i64> {
+    let candidate: Vec<char> = candidate.chars().collect();
+    let mut score: i64 = 0;
+    let mut next: usize = 0;
+    let mut previous_match: Option<usize> = None;
+    for query_char in query.chars() {
+        let offset = candidate
+            .get(next..)?
+            .iter()
+            .position(|c| c.to_lowercase().eq(query_char.to_lowercase()))?;
+        let index = next.saturating_add(offset);
+        score = score.saturating_add(1);
+        if previous_match.is_some_and(|previous| previous.saturating_add(1) == index) {
+            score = score.saturating_add(5);
+        } else if previous_match.is_some() {
+            score = score.saturating_sub(i64::try_from(offset.min(MAX_GAP_PENALTY)).unwrap_or(0));
+        }
+        let previous_char = index.checked_sub(1).and_then(|previous| candidate.get(previous));
+        let is_boundary = match (previous_char, candidate.get(index)) {
+            (None, _) => true,
+            (Some(previous), Some(current)) => {
+                matches!(previous, '/' | '\\' | '_' | '-' | '.' | ' ')
+                    || (previous.is_lowercase() && current.is_uppercase())
+            }
+            (Some(_), None) => false,
+        };
+        if is_boundary {
+            score = score.saturating_add(8);
+        }
+        previous_match = Some(index);
+        next = index.saturating_add(1);
+    }
+    Some(score)
+}
+
+fn walk(dir: &Path, prefix: &str, ignore_hidden: bool, files: &mut Vec<String>) -> Result<(), Error> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name == ".git" || (ignore_hidden && name.starts_with('.')) {
+            continue;
+        }
+        let relative = format!("{prefix}{name}");
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            walk(&entry.path(), &format!("{relative}/"), ignore_hidden, files)?;
+        } else if !file_type.is_symlink() {
+            files.push(relative);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_super {
+    use super::*;
+
+    #[test]
+    fn fuzzy_score_ranks_boundaries_and_runs() {
+        let document = fuzzy_score("src/document.rs", "docrs");
+        assert!(document.is_some());
+        assert!(document > fuzzy_score("src/other/ducoment.rs", "docrs"));
+        assert!(document > fuzzy_score("src/dxoxcxrxs.rs", "docrs"));
+        assert!(fuzzy_score("src/HighlightingOptions.rs", "ho") > fuzzy_score("src/phonebook.rs", "ho"));
+        assert_eq!(fuzzy_score("src/row.rs", "xyz"), None);
+        assert_eq!(fuzzy_score("src/row.rs", ""), Some(0));
+    }
+
+    #[test]
+    fn list_files_skips_hidden() {
+        let root = std::env::temp_dir().join("hammare_list_files");
+        if root.exists() {
+            fs::remove_dir_all(&root).expect("Failed removing directory");
+        }
+        for dir in ["src/nested", ".git", ".cache"] {
+            fs::create_dir_all(root.join(dir)).expect("Failed creating directory");
+        }
+        for file in ["README.md", "src/main.rs", "src/nested/row.rs", ".hidden", ".git/HEAD", ".cache/data"] {
+            fs::write(root.join(file), "").expect("Failed writing file");
+        }
+        let root_name = root.to_str().expect("Failed converting path");
+
+        let visible = list_files(root_name, true).expect("Failed listing files");
+        assert_eq!(visible, vec!["README.md", "src/main.rs", "src/nested/row.rs"]);
+        let all = list_files(root_name, false).expect("Failed listing files");
+        assert_eq!(all, vec![".cache/data", ".hidden", "README.md", "src/main.rs", "src/nested/row.rs"]);
+        fs::remove_dir_all(&root).expect("Failed removing directory");
+    }
+}
@@ -1,16 +1,73 @@
 use crate::highlighting;
 use crate::HighlightingOptions;
 use crate::SearchDirection;
-use std::cmp;
+use std::hash::{Hash, Hasher};
+use std::mem;
 use termion::color;
 use unicode_segmentation::UnicodeSegmentation;
 
-#[derive(Default)]
+/// The characters words are made of, for word navigation, word deletion and
+/// whole-word search. Letters and digits are always word characters.
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Default)]
+#[non_exhaustive]
+pub enum WordCharSet {
+    /// Identifier characters, so `_` is part of a word
+    #[default]
+    AsciiIdent,
+    /// Identifier characters and `-`, e.g. for CSS or Lisp
+    IncludingDash,
+}
+
+/// How control characters other than tabs, such as `\x07`, are displayed
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Default)]
+#[non_exhaustive]
+pub enum ControlCharDisplay {
+    /// In caret notation, e.g. `^G`, two columns wide
+    #[default]
+    Caret,
+    /// Not at all, taking no column
+    Hidden,
+}
+
+impl WordCharSet {
+    /// Checks if `c` is part of a word with this set
+    #[must_use]
+    pub fn is_word_char(self, c: char) -> bool {
+        match self {
+            Self::AsciiIdent => c == '_' || !is_separator(c),
+            Self::IncludingDash => c == '_' || c == '-' || !is_separator(c),
+        }
+    }
+
+    fn is_word_grapheme(self, grapheme: &str) -> bool {
+        grapheme.chars().next().is_some_and(|c| self.is_word_char(c))
+    }
+}
+
+#[derive(Default, Clone)]
+#[allow(clippy::partial_pub_fields)]
 pub struct Row {
     string: String,
     pub is_highlighted: bool,
     highlighting: Vec<highlighting::Type>,
     len: usize,
+    /// The states the row was last highlighted from and ended in
+    hl_states: (highlighting::HlState, highlighting::HlState),
+}
+
+/// Rows are compared by their text only, not by their highlighting state
+impl PartialEq for Row {
+    fn eq(&self, other: &Self) -> bool {
+        self.string == other.string
+    }
+}
+
+impl Eq for Row {}
+
+impl Hash for Row {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.string.hash(state);
+    }
 }
 
 impl From<&str> for Row {
@@ -20,48 +77,68 @@ impl From<&str> for Row {
             highlighting: Vec::new(),
             is_highlighted: false,
             len: slice.graphemes(true).count(),
+            hl_states: Default::default(),
         }
     }
 }
 
 impl Row {
+    /// Returns the graphemes displayed from column `start` to column `end`
+    /// with their colors, measured like in `visual_column`: tabs are
+    /// expanded to spaces, control characters shown as set by `controls` and
+    /// zero-width characters left out. A grapheme that does not fit whole
+    /// between the columns is left out, except for the part of a tab that does.
     #[must_use]
-    pub fn render(&self, start: usize, end: usize) -> String {
-        let end = cmp::min(end, self.string.len());
-        let start = cmp::min(start, end);
+    pub fn render(&self, start: usize, end: usize, tab_width: usize, controls: ControlCharDisplay) -> String {
         let mut result = String::new();
         let mut current_highlighting = &highlighting::Type::None;
-        #[allow(clippy::integer_arithmetic)]
-        #[allow(clippy::string_slice)]
-        for (index, grapheme) in self.string[..]
-            .graphemes(true)
-            .enumerate()
-            .skip(start)
-            .take(end - start)
-        {
-            if let Some(c) = grapheme.chars().next() {
-                let highlighting_type = self
-                    .highlighting
-                    .get(index)
-                    .unwrap_or(&highlighting::Type::None);
-
-                if highlighting_type != current_highlighting {
-                    current_highlighting = highlighting_type;
-                    let start_highlight =
-                        format!("{}", termion::color::Fg(highlighting_type.to_color()));
-                    result.push_str(&*start_highlight);
+        let mut column: usize = 0;
+        for (index, grapheme) in self.string.graphemes(true).enumerate() {
+            let grapheme_start = column;
+            column = column.saturating_add(grapheme_width(grapheme, column, tab_width, controls));
+            if grapheme_start >= end {
+                break;
+            }
+            let Some(c) = grapheme.chars().next() else {
+                continue;
+            };
+            if c != '\t' && (grapheme_start < start || column > end) {
+                continue;
+            }
+            let visible = column.min(end).saturating_sub(grapheme_start.max(start));
+            if c == '\t' && visible == 0 {
+                continue;
+            }
+            let highlighting_type = self.highlighting.get(index).unwrap_or(&highlighting::Type::None);
+
+            if highlighting_type != current_highlighting {
+                if *current_highlighting == highlighting::Type::TrailingWhitespace {
+                    let end_background = format!("{}", color::Bg(color::Reset));
+                    result.push_str(&end_background);
+                }
+                current_highlighting = highlighting_type;
+                let start_highlight = format!("{}", termion::color::Fg(highlighting_type.to_color()));
+                result.push_str(&start_highlight);
+                if *highlighting_type == highlighting::Type::TrailingWhitespace {
+                    let start_background = format!("{}", color::Bg(highlighting_type.to_color()));
+                    result.push_str(&start_background);
                 }
+            }
 
-                if c == '\t' {
-                    result.push(' ');
-                } else {
-                    result.push(c);
+            if c == '\t' {
+                result.push_str(&" ".repeat(visible));
+            } else if c.is_ascii_control() {
+                if let (ControlCharDisplay::Caret, Ok(byte)) = (controls, u8::try_from(c)) {
+                    result.push('^');
+                    result.push(char::from(byte ^ 0x40));
                 }
+            } else if !is_zero_width(c) {
+                result.push_str(grapheme);
             }
         }
-        let end_highlight = format!("{}", termion::color::Fg(color::Reset));
+        let end_highlight = format!("{}{}", termion::color::Fg(color::Reset), color::Bg(color::Reset));
         #[allow(clippy::string_slice)]
-        result.push_str(&*end_highlight);
+        result.push_str(&end_highlight);
         result
     }
 
@@ -77,6 +154,63 @@ impl Row {
         self.len == 0
     }
 
+    /// Returns the column at which the grapheme `x` is displayed, with tabs
+    /// expanded to the next multiple of `tab_width`, control characters as
+    /// wide as `controls` displays them, zero-width characters taking no
+    /// column and East Asian wide characters two
+    #[must_use]
+    pub fn visual_column(&self, x: usize, tab_width: usize, controls: ControlCharDisplay) -> usize {
+        self.string.graphemes(true).take(x).fold(0, |column: usize, grapheme| {
+            column.saturating_add(grapheme_width(grapheme, column, tab_width, controls))
+        })
+    }
+
+    /// Returns the index of the grapheme displayed at `column`, measured
+    /// like in `visual_column`, or the length of the row if `column` is past
+    /// its end. This is the inverse of `visual_column`.
+    #[must_use]
+    pub fn x_at_column(&self, column: usize, tab_width: usize, controls: ControlCharDisplay) -> usize {
+        let mut start: usize = 0;
+        for (x, grapheme) in self.string.graphemes(true).enumerate() {
+            start = start.saturating_add(grapheme_width(grapheme, start, tab_width, controls));
+            if column < start {
+                return x;
+            }
+        }
+        self.len
+    }
+
+    /// Returns the grapheme ranges, end excluded, of the segments the row is
+    /// displayed in when soft-wrapped at `width` columns, measured like in
+    /// `visual_column`. An empty row still takes one segment, and a grapheme
+    /// wider than `width` one of its own.
+    #[must_use]
+    pub fn wrap(&self, width: usize, tab_width: usize, controls: ControlCharDisplay) -> Vec<(usize, usize)> {
+        let width = width.max(1);
+        let mut segments = Vec::new();
+        let mut start: usize = 0;
+        let mut column: usize = 0;
+        let mut segment_width: usize = 0;
+        for (x, grapheme) in self.string.graphemes(true).enumerate() {
+            let grapheme_width = grapheme_width(grapheme, column, tab_width, controls);
+            if segment_width > 0 && segment_width.saturating_add(grapheme_width) > width {
+                segments.push((start, x));
+                start = x;
+                segment_width = 0;
+            }
+            column = column.saturating_add(grapheme_width);
+            segment_width = segment_width.saturating_add(grapheme_width);
+        }
+        segments.push((start, self.len));
+        segments
+    }
+
+    /// Checks if a row is empty or only contains whitespace
+    #[must_use]
+    pub fn is_blank(&self) -> bool {
+        self.string.chars().all(char::is_whitespace)
+    }
+
     /// Inserts a new character into the selected row
     #[allow(clippy::string_slice)]
     pub fn insert(&mut self, at: usize, c: char) {
@@ -85,7 +219,7 @@ impl Row {
             self.len = self.len.saturating_add(1);
             return;
         }
-        let mut result: String = String::new();
+        let mut result = String::new();
         let mut length: usize = 0;
         for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
             length = length.saturating_add(1);
@@ -99,13 +233,85 @@ impl Row {
         self.string = result;
     }
 
+    /// Inserts `c` like `insert`, updating the highlighting in place instead
+    /// of leaving the row to be highlighted again, when `c` is a letter or a
+    /// digit typed between two others of the same plain, string, comment or
+    /// number run, which can not change how the row is tokenized. Returns
+    /// `false`, without inserting, otherwise, e.g. for quotes, brackets,
+    /// comment markers, whitespace or a word that could become a keyword.
+    /// The row is not rebuilt, `c` is inserted into its string in place.
+    pub fn insert_in_word(&mut self, at: usize, c: char, opts: &HighlightingOptions) -> bool {
+        if !self.is_highlighted || opts.markdown() || !c.is_alphanumeric() || at == 0 || at >= self.len {
+            return false;
+        }
+        let Some((byte, next_grapheme)) = self.string.grapheme_indices(true).nth(at) else {
+            return false;
+        };
+        let (before, after) = self.string.split_at(byte);
+        let (Some(prev), Some(next), Some(prev_grapheme)) =
+            (before.chars().next_back(), after.chars().next(), before.graphemes(true).next_back())
+        else {
+            return false;
+        };
+        let index = before.chars().count();
+        let (Some(&prev_type), Some(&next_type)) = (
+            self.highlighting.get(index.saturating_sub(1)),
+            self.highlighting.get(index),
+        ) else {
+            return false;
+        };
+        let same_run = match next_type {
+            highlighting::Type::None
+            | highlighting::Type::String
+            | highlighting::Type::Comment
+            | highlighting::Type::MultilineComment => true,
+            highlighting::Type::Number => c.is_ascii_digit(),
+            _ => false,
+        };
+        if !same_run || prev_type != next_type || !prev.is_alphanumeric() || !next.is_alphanumeric() {
+            return false;
+        }
+        if format!("{prev_grapheme}{c}{next_grapheme}").graphemes(true).count() != 3 {
+            return false;
+        }
+        let is_word_char = |&(_, neighbour): &(usize, char)| !is_separator(neighbour) || neighbour == '_';
+        let start = before
+            .char_indices()
+            .rev()
+            .take_while(is_word_char)
+            .last()
+            .map_or(byte, |(start, _)| start);
+        let end = after
+            .char_indices()
+            .find(|pair| !is_word_char(pair))
+            .map_or(after.len(), |(end, _)| end);
+        let word = format!(
+            "{}{c}{}",
+            before.get(start..).unwrap_or_default(),
+            after.get(..end).unwrap_or_default()
+        );
+        let could_match = opts
+            .primary_keywords()
+            .iter()
+            .chain(opts.secondary_keywords())
+            .chain(opts.annotations())
+            .any(|keyword| word.contains(keyword.as_str()));
+        if could_match {
+            return false;
+        }
+        self.string.insert(byte, c);
+        self.len = self.len.saturating_add(1);
+        self.highlighting.insert(index, next_type);
+        true
+    }
+
     /// Deletes a row at a given position
     #[allow(clippy::string_slice)]
     pub fn delete(&mut self, at: usize) {
         if at >= self.len() {
             return;
         }
-        let mut result: String = String::new();
+        let mut result = String::new();
         let mut length: usize = 0;
         for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
             if index != at {
@@ -117,21 +323,24 @@ impl Row {
         self.string = result;
     }
 
-    /// Appends a row to a given `Row`
+    /// Appends a row to a given `Row`. The length is counted again since the
+    /// last grapheme of the row and the first one of `new` may combine.
     pub fn append(&mut self, new: &Self) {
-        self.string = format!("{}{}", self.string, new.string);
-        self.len = self.len.saturating_add(new.len);
+        self.string.push_str(&new.string);
+        self.len = self.string.graphemes(true).count();
+        self.is_highlighted = false;
     }
 
-    /// Splits a row by its given position
+    /// Splits the row before the grapheme `at` and returns the part after it.
+    /// At 0 the whole row moves to the new one, at or past the end the new
+    /// row is empty.
     #[must_use]
-    #[allow(clippy::string_slice)]
     pub fn split(&mut self, at: usize) -> Self {
-        let mut row: String = String::new();
+        let mut row = String::new();
         let mut length: usize = 0;
-        let mut splitted_row: String = String::new();
+        let mut splitted_row = String::new();
         let mut splitted_length: usize = 0;
-        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
+        for (index, grapheme) in self.string.graphemes(true).enumerate() {
             if index < at {
                 length = length.saturating_add(1);
                 row.push_str(grapheme);
@@ -149,7 +358,125 @@ impl Row {
             len: splitted_length,
             is_highlighted: false,
             highlighting: Vec::new(),
+            hl_states: Default::default(),
+        }
+    }
+
+    /// Returns the graphemes between `start` and `end`
+    #[must_use]
+    pub fn slice(&self, start: usize, end: usize) -> String {
+        self.string
+            .graphemes(true)
+            .skip(start)
+            .take(end.saturating_sub(start))
+            .collect()
+    }
+
+    /// Returns the index right after the end of the word at or following `at`,
+    /// words being made of `word_chars`
+    #[must_use]
+    pub fn word_end(&self, at: usize, word_chars: WordCharSet) -> usize {
+        let mut graphemes = self.string.graphemes(true).skip(at).peekable();
+        let mut index = at;
+        while graphemes.next_if(|grapheme| !word_chars.is_word_grapheme(grapheme)).is_some() {
+            index = index.saturating_add(1);
+        }
+        while graphemes.next_if(|grapheme| word_chars.is_word_grapheme(grapheme)).is_some() {
+            index = index.saturating_add(1);
+        }
+        index
+    }
+
+    /// Returns the start and end, end excluded, of the word the grapheme `at`
+    /// is part of, or `None` if it is not one of `word_chars` or past the end
+    /// of the row
+    #[must_use]
+    pub fn word_bounds(&self, at: usize, word_chars: WordCharSet) -> Option<(usize, usize)> {
+        let graphemes: Vec<&str> = self.string.graphemes(true).collect();
+        let is_word = |index: usize| graphemes.get(index).is_some_and(|grapheme| word_chars.is_word_grapheme(grapheme));
+        if !is_word(at) {
+            return None;
+        }
+        let mut start = at;
+        while start > 0 && is_word(start.saturating_sub(1)) {
+            start = start.saturating_sub(1);
+        }
+        let mut end = at.saturating_add(1);
+        while is_word(end) {
+            end = end.saturating_add(1);
         }
+        Some((start, end))
+    }
+
+    /// Returns the highlighting type of each character of the row, as of the
+    /// last call to `highlight`
+    #[must_use]
+    pub fn highlighting(&self) -> &[highlighting::Type] {
+        &self.highlighting
+    }
+
+    /// Returns the highlighting type of the grapheme `x`, as of the last call
+    /// to `highlight`
+    #[must_use]
+    pub fn highlight_at(&self, x: usize) -> Option<highlighting::Type> {
+        self.highlighting.get(self.char_index(x)).copied()
+    }
+
+    /// Replaces the highlighting type of the grapheme `x`, e.g. to mark it
+    /// over its syntax color, and returns the type it had, or `None` if the
+    /// row is not highlighted that far
+    pub fn set_highlight(&mut self, x: usize, hl_type: highlighting::Type) -> Option<highlighting::Type> {
+        let index = self.char_index(x);
+        self.highlighting.get_mut(index).map(|element| mem::replace(element, hl_type))
+    }
+
+    /// Returns the index of the first character of the grapheme `x`, at
+    /// which its highlighting type is stored
+    fn char_index(&self, x: usize) -> usize {
+        self.string.graphemes(true).take(x).map(|grapheme| grapheme.chars().count()).sum()
+    }
+
+    /// Checks if the row only holds a comment, as of the last call to
+    /// `highlight`
+    #[must_use]
+    pub fn is_comment(&self) -> bool {
+        !self.is_blank()
+            && self.highlighting.len() == self.string.chars().count()
+            && self.string.chars().zip(&self.highlighting).all(|(c, hl_type)| {
+                c.is_whitespace()
+                    || matches!(
+                        hl_type,
+                        highlighting::Type::Comment | highlighting::Type::MultilineComment | highlighting::Type::Annotation
+                    )
+            })
+    }
+
+    /// Returns the highlighting type at `col` and how many graphemes in a row
+    /// share it from there, e.g. to draw them with a single color change.
+    /// Past the highlighted part of the row it returns `None` and `0`.
+    #[must_use]
+    pub fn highlight_run(&self, col: usize) -> (highlighting::Type, usize) {
+        let mut index = self.char_index(col);
+        let Some(&hl_type) = self.highlighting.get(index) else {
+            return (highlighting::Type::None, 0);
+        };
+        let len = self
+            .string
+            .graphemes(true)
+            .skip(col)
+            .take_while(|grapheme| {
+                let same = self.highlighting.get(index) == Some(&hl_type);
+                index = index.saturating_add(grapheme.chars().count());
+                same
+            })
+            .count();
+        (hl_type, len)
+    }
+
+    /// Returns the Row's contents as a string slice
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.string
     }
 
     /// Returns a byte slice of the Row's `String`'s contents
@@ -159,26 +486,50 @@ impl Row {
     }
 
     /// Returns the elements that correponds to the search query and direction
-    /// passed
+    /// passed. With `whole_word`, a match only counts if it is not preceded or
+    /// followed by one of the given word characters.
+    ///
+    /// Searching forward returns the first match starting at or after `at`, so
+    /// to step through non-overlapping matches pass the end of the previous
+    /// match rather than its start. Searching backward returns the last match
+    /// starting strictly before `at`, even if it goes on past `at`, so a match
+    /// starting at `at` is never found again. An empty query never matches.
     #[must_use]
-    #[allow(clippy::string_slice)]
-    pub fn find(&self, query: &str, at: usize, direction: SearchDirection) -> Option<usize> {
-        if at > self.len || query.is_empty() {
+    pub fn find(&self, query: &str, at: usize, direction: SearchDirection, whole_word: Option<WordCharSet>) -> Option<usize> {
+        if query.is_empty() || (direction == SearchDirection::Forward && at > self.len) {
             return None;
         }
 
-        let start = if direction == SearchDirection::Forward {
+        let query_len = query.graphemes(true).count();
+        let mut start = if direction == SearchDirection::Forward {
             at
         } else {
             0
         };
 
-        let end = if direction == SearchDirection::Forward {
+        let mut end = if direction == SearchDirection::Forward {
             self.len
         } else {
-            at
+            at.min(self.len).saturating_add(query_len).saturating_sub(1)
         };
 
+        loop {
+            let index = self.find_between(query, start, end, direction)?;
+            if whole_word.is_none_or(|word_chars| self.is_whole_word(index, query_len, word_chars)) {
+                return Some(index);
+            }
+            if direction == SearchDirection::Forward {
+                start = index.saturating_add(1);
+            } else {
+                end = index.saturating_add(query_len).saturating_sub(1);
+            }
+        }
+    }
+
+    /// Returns the first or last match of `query` lying between the graphemes
+    /// `start` and `end`
+    #[allow(clippy::string_slice)]
+    fn find_between(&self, query: &str, start: usize, end: usize, direction: SearchDirection) -> Option<usize> {
         let substring: String = self.string[..]
             .graphemes(true)
             .skip(start)
@@ -204,28 +555,106 @@ impl Row {
         None
     }
 
+    /// Checks if the `len` graphemes at `at` are bounded by graphemes that
+    /// are not `word_chars` or the edges of the row
+    fn is_whole_word(&self, at: usize, len: usize, word_chars: WordCharSet) -> bool {
+        let mut graphemes = self.string.graphemes(true);
+        let before = at.checked_sub(1).and_then(|index| graphemes.nth(index));
+        let after = graphemes.nth(len);
+        let is_word = |grapheme: &str| word_chars.is_word_grapheme(grapheme);
+        !before.is_some_and(is_word) && !after.is_some_and(is_word)
+    }
+
+    /// Returns the grapheme ranges, end excluded, of the non-overlapping
+    /// matches of `query` in the row
+    #[must_use]
+    pub fn match_ranges(&self, query: &str, case_sensitive: bool) -> Vec<(usize, usize)> {
+        let query: Vec<&str> = query.graphemes(true).collect();
+        let graphemes: Vec<&str> = self.string.graphemes(true).collect();
+        let same = |a: &str, b: &str| {
+            if case_sensitive {
+                a == b
+            } else {
+                a.to_lowercase() == b.to_lowercase()
+            }
+        };
+        let mut ranges = Vec::new();
+        if query.is_empty() {
+            return ranges;
+        }
+        let mut index: usize = 0;
+        while let Some(window) = graphemes.get(index..index.saturating_add(query.len())) {
+            if window.iter().zip(&query).all(|(a, b)| same(a, b)) {
+                let end = index.saturating_add(query.len());
+                ranges.push((index, end));
+                index = end;
+            } else {
+                index = index.saturating_add(1);
+            }
+        }
+        ranges
+    }
+
     /// Highlight the matches found when user searchs for an element
+    #[allow(clippy::ref_option)]
     fn highlight_match(&mut self, word: &Option<String>) {
-        if let Some(ref word) = *word {
-            if word.is_empty() {
-                return;
-            }
-            let mut index = 0;
-            while let Some(search_match) = self.find(word, index, SearchDirection::Forward) {
-                if let Some(next_index) = search_match.checked_add(word.get(..).expect("Failed while trying to get slice of `word`").graphemes(true).count()) {
-                    for i in search_match..next_index {
-                       if let Some(element) = self.highlighting.get_mut(i) {
-                           *element = highlighting::Type::Match;
-                       }
+        if let Some(word) = word.as_deref() {
+            for (start, end) in self.match_ranges(word, true) {
+                for i in start..end {
+                    if let Some(element) = self.highlighting.get_mut(i) {
+                        *element = highlighting::Type::Match;
                     }
-                    index = next_index;
-                } else {
-                    break;
                 }
             }
         }
     }
 
+    /// Marks the annotations of `opts`, such as `TODO`, that are whole words
+    /// within a comment
+    fn highlight_annotations(&mut self, opts: &HighlightingOptions, chars: &[char]) {
+        let is_comment = |hl_type: Option<&highlighting::Type>| {
+            matches!(hl_type, Some(highlighting::Type::Comment | highlighting::Type::MultilineComment))
+        };
+        let is_word_char = |index: Option<usize>| index.and_then(|index| chars.get(index)).is_some_and(|c| c.is_alphanumeric() || *c == '_');
+        for annotation in opts.annotations() {
+            let annotation: Vec<char> = annotation.chars().collect();
+            if annotation.is_empty() {
+                continue;
+            }
+            let len = annotation.len();
+            for start in 0..=chars.len().saturating_sub(len) {
+                let end = start.saturating_add(len);
+                if chars.get(start..end) != Some(annotation.as_slice())
+                    || !(start..end).all(|index| is_comment(self.highlighting.get(index)))
+                    || is_word_char(start.checked_sub(1))
+                    || is_word_char(Some(end))
+                {
+                    continue;
+                }
+                for hl_type in self.highlighting.iter_mut().skip(start).take(len) {
+                    *hl_type = highlighting::Type::Annotation;
+                }
+            }
+        }
+    }
+
+    /// Marks the run of spaces and tabs at the end of the row, if enabled
+    fn highlight_trailing_whitespace(&mut self, opts: &HighlightingOptions) {
+        if !opts.highlight_trailing_whitespace() {
+            return;
+        }
+        let count = self
+            .string
+            .chars()
+            .rev()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .count();
+        let start = self.highlighting.len().saturating_sub(count);
+        for element in self.highlighting.iter_mut().skip(start) {
+            *element = highlighting::Type::TrailingWhitespace;
+        }
+    }
+
     /// Highligh a substring with a given type.
     fn highlight_str(&mut self, index: &mut usize, substring: &str, chars: &[char], hl_type: highlighting::Type) -> bool {
         if substring.is_empty() {
@@ -307,16 +736,18 @@ impl Row {
 
     /// Returns a boolean and does the logic to highlight a comment
     fn highlight_comment(&mut self, index: &mut usize, opts: &HighlightingOptions, c: char, chars: &[char]) -> bool {
-        if opts.comments() && c == '/' && *index < chars.len() {
-            if let Some(next_char) = chars.get(index.saturating_add(1)) {
-                if *next_char == '/' {
-                    for _ in *index..chars.len() {
-                        self.highlighting.push(highlighting::Type::Comment);
-                        *index = index.saturating_add(1);
-                    }
-                    return true;
-                }
-            };
+        let Some(marker) = opts.line_comment().filter(|_| opts.comments()) else {
+            return false;
+        };
+        let is_marker = chars
+            .get(*index..)
+            .is_some_and(|rest| rest.iter().copied().take(marker.chars().count()).eq(marker.chars()));
+        if marker.starts_with(c) && is_marker {
+            for _ in *index..chars.len() {
+                self.highlighting.push(highlighting::Type::Comment);
+                *index = index.saturating_add(1);
+            }
+            return true;
         }
         false
     }
@@ -325,13 +756,10 @@ impl Row {
         if opts.comments() && c == '/' && *index < chars.len() {
             if let Some(next_char) = chars.get(index.saturating_add(1)) {
                 if *next_char == '*' {
-                    let indexed = self.string.get(index.saturating_add(2)..2).expect("Failed while trying to indexing string.").find("*/");
-
-                    let closing_index = if let Some(closing_index) = indexed {
-                        index.saturating_add(closing_index).saturating_add(4)
-                    } else {
-                        chars.len()
-                    };
+                    let closing_index = chars
+                        .get(index.saturating_add(2)..)
+                        .and_then(|rest| rest.windows(2).position(|pair| pair == ['*', '/']))
+                        .map_or(chars.len(), |closing_index| index.saturating_add(closing_index).saturating_add(4));
 
                     for _ in *index..closing_index {
                         self.highlighting.push(highlighting::Type::MultilineComment);
@@ -339,7 +767,7 @@ impl Row {
                     }
                     return true;
                 }
-            };
+            }
         }
         false
     }
@@ -390,27 +818,150 @@ impl Row {
         false
     }
 
+    /// Returns a boolean and does the logic to highlight punctuation
+    fn highlight_punctuation(&mut self, index: &mut usize, opts: &HighlightingOptions, c: char) -> bool {
+        if opts.punctuation() && (c == ':' || c == ',') {
+            self.highlighting.push(highlighting::Type::Punctuation);
+            *index = index.saturating_add(1);
+            return true;
+        }
+        false
+    }
+
+    /// Colors the brackets and braces that are not part of a string or a
+    /// comment by their nesting depth, starting at `depth`, and returns the
+    /// depth at the end of the row. Brackets marked as a search match or as
+    /// the matching bracket keep that color but still count for the depth.
+    pub fn highlight_brackets(&mut self, mut depth: usize) -> usize {
+        for (c, hl_type) in self.string.chars().zip(self.highlighting.iter_mut()) {
+            let Some((color_depth, next_depth)) = bracket_depths(c, *hl_type, depth) else {
+                continue;
+            };
+            if matches!(*hl_type, highlighting::Type::None | highlighting::Type::Bracket(_)) {
+                *hl_type = highlighting::Type::Bracket(color_depth);
+            }
+            depth = next_depth;
+        }
+        depth
+    }
+
+    /// Returns the depth at the end of the row when its brackets start at
+    /// `depth`, and whether they are already colored for it, without
+    /// touching the highlighting, see `highlight_brackets`
+    #[must_use]
+    pub fn bracket_depth(&self, mut depth: usize) -> (usize, bool) {
+        let mut colored = true;
+        for (c, hl_type) in self.string.chars().zip(self.highlighting.iter()) {
+            let Some((color_depth, next_depth)) = bracket_depths(c, *hl_type, depth) else {
+                continue;
+            };
+            if matches!(*hl_type, highlighting::Type::None | highlighting::Type::Bracket(_)) {
+                colored &= *hl_type == highlighting::Type::Bracket(color_depth);
+            }
+            depth = next_depth;
+        }
+        (depth, colored)
+    }
+
+    /// Highlights the row as Markdown and returns the state it ends in. Code
+    /// fences and the rows between them are highlighted as code only.
+    fn highlight_markdown(&mut self, start_state: highlighting::HlState, chars: &[char]) -> highlighting::HlState {
+        let trimmed = self.string.trim_start();
+        let is_fence = trimmed.starts_with("```");
+        if start_state == highlighting::HlState::InCodeFence || is_fence {
+            self.highlighting = vec![highlighting::Type::Code; chars.len()];
+            return if (start_state == highlighting::HlState::InCodeFence) == is_fence {
+                highlighting::HlState::Normal
+            } else {
+                highlighting::HlState::InCodeFence
+            };
+        }
+        let level = trimmed.chars().take_while(|c| *c == '#').count();
+        if (1..=6).contains(&level) && trimmed.chars().nth(level).is_none_or(char::is_whitespace) {
+            self.highlighting = vec![highlighting::Type::Heading; chars.len()];
+            return highlighting::HlState::Normal;
+        }
+
+        self.highlighting = vec![highlighting::Type::None; chars.len()];
+        let mut index = 0;
+        while let Some(c) = chars.get(index) {
+            let marker_len = match *c {
+                '`' => 1,
+                '*' | '_' => chars
+                    .get(index..)
+                    .map_or(1, |rest| rest.iter().take(2).take_while(|next| *next == c).count()),
+                _ => 0,
+            };
+            let after_word = *c == '_' && index > 0 && chars.get(index.saturating_sub(1)).is_some_and(|prev| prev.is_alphanumeric());
+            let marker = chars.get(index..index.saturating_add(marker_len)).unwrap_or_default();
+            let closing = chars
+                .get(index.saturating_add(marker_len).saturating_add(1)..)
+                .and_then(|rest| rest.windows(marker_len.max(1)).position(|window| window == marker));
+            let Some(closing) = closing.filter(|_| marker_len > 0 && !after_word) else {
+                index = index.saturating_add(1);
+                continue;
+            };
+            let end = index
+                .saturating_add(marker_len.saturating_mul(2))
+                .saturating_add(1)
+                .saturating_add(closing);
+            let hl_type = if *c == '`' {
+                highlighting::Type::Code
+            } else {
+                highlighting::Type::Emphasis
+            };
+            for element in self.highlighting.iter_mut().take(end).skip(index) {
+                *element = hl_type;
+            }
+            index = end;
+        }
+        highlighting::HlState::Normal
+    }
+
+    /// Checks if `highlight` would recompute the row when called with
+    /// `word` and `start_state`, rather than leave it as it is
+    #[must_use]
+    pub fn needs_highlight(&self, word: &Option<String>, start_state: highlighting::HlState) -> bool {
+        !self.is_highlighted || word.is_some() || self.hl_states.0 != start_state
+    }
+
+    /// Returns the state the row ended in when it was last highlighted
+    #[must_use]
+    pub fn end_state(&self) -> highlighting::HlState {
+        self.hl_states.1
+    }
+
     /// Check if any of the `HighlightingOptions` applies and if not,
     /// pushes to the `highlighting` vec `None`
-    pub fn highlight(&mut self, opts: &HighlightingOptions, word: &Option<String>, start_with_comment: bool) -> bool {
-        let chars: Vec<char> = self.string.chars().collect();
-        if self.is_highlighted && word.is_none() {
-            if let Some(hl_type) = self.highlighting.last() {
-                if *hl_type == highlighting::Type::MultilineComment && self.string.get(self.string.len().saturating_sub(2)..).expect("Failed while indexing string.") == "*/" {
-                    return true;
-                }
-            }
-            return false;
+    ///
+    /// `start_state` is the state the previous row ended in, and the state
+    /// this row ends in is returned to be passed to the next one. A row that
+    /// is already highlighted from the same state is left as it is.
+    ///
+    /// # Panics
+    ///
+    /// It will panic if the end of the row's string can not be indexed.
+    pub fn highlight(&mut self, opts: &HighlightingOptions, word: &Option<String>, start_state: highlighting::HlState) -> highlighting::HlState {
+        if !self.needs_highlight(word, start_state) {
+            return self.end_state();
         }
+        let chars: Vec<char> = self.string.chars().collect();
         self.highlighting = Vec::new();
+        if opts.markdown() {
+            let end_state = self.highlight_markdown(start_state, &chars);
+            self.highlight_trailing_whitespace(opts);
+            self.highlight_match(word);
+            self.hl_states = (start_state, end_state);
+            self.is_highlighted = true;
+            return end_state;
+        }
         let mut index = 0;
-        let mut in_ml_comment = start_with_comment;
+        let mut in_ml_comment = start_state == highlighting::HlState::InBlockComment;
         if in_ml_comment {
-            let closing_index = if let Some(closing_index) = self.string.find("*/") {
-                closing_index.saturating_add(2)
-            } else {
-                chars.len()
-            };
+            let closing_index = chars
+                .windows(2)
+                .position(|pair| pair == ['*', '/'])
+                .map_or(chars.len(), |closing_index| closing_index.saturating_add(2));
             for _ in 0..closing_index {
                 self.highlighting.push(highlighting::Type::MultilineComment);
             }
@@ -423,7 +974,7 @@ impl Row {
             }
             in_ml_comment = false;
 
-            if self.highlight_char(&mut index, opts, *c, &chars) || self.highlight_comment(&mut index, opts, *c, &chars) || self.highlight_primary_keywords(&mut index, opts, &chars) || self.highlight_secondary_keywords(&mut index, opts, &chars) || self.highlight_string(&mut index, opts, *c, &chars) || self.highlight_number(&mut index, opts, *c, &chars) {
+            if self.highlight_char(&mut index, opts, *c, &chars) || self.highlight_comment(&mut index, opts, *c, &chars) || self.highlight_primary_keywords(&mut index, opts, &chars) || self.highlight_secondary_keywords(&mut index, opts, &chars) || self.highlight_string(&mut index, opts, *c, &chars) || self.highlight_number(&mut index, opts, *c, &chars) || self.highlight_punctuation(&mut index, opts, *c) {
                 continue;
             }
 
@@ -434,18 +985,93 @@ impl Row {
 
             index = index.saturating_add(1);
         }
+        self.highlight_trailing_whitespace(opts);
+        self.highlight_annotations(opts, &chars);
         self.highlight_match(word);
         let comment_range = self.string.get(self.string.len().saturating_sub(2)..).expect("Failed while trying to index string");
-        
-        if in_ml_comment && comment_range != "*/" {
-            return true;
-        }
+
+        let end_state = if in_ml_comment && comment_range != "*/" {
+            highlighting::HlState::InBlockComment
+        } else {
+            highlighting::HlState::Normal
+        };
+        self.hl_states = (start_state, end_state);
         self.is_highlighted = true;
-        false
+        end_state
     }
 
 }
 
+/// Returns the number of columns `grapheme` takes when displayed at
+/// `column`, see `Row::visual_column`
+fn grapheme_width(grapheme: &str, column: usize, tab_width: usize, controls: ControlCharDisplay) -> usize {
+    match grapheme.chars().next() {
+        Some('\t') => {
+            let tab_width = tab_width.max(1);
+            tab_width.saturating_sub(column.checked_rem(tab_width).unwrap_or(0))
+        }
+        Some(c) if c.is_ascii_control() => match controls {
+            ControlCharDisplay::Caret => 2,
+            ControlCharDisplay::Hidden => 0,
+        },
+        _ if grapheme.chars().all(is_zero_width) => 0,
+        Some(c) if is_wide(c) => 2,
+        _ => 1,
+    }
+}
+
+/// Checks if `c` is an East Asian wide or fullwidth character, such as CJK
+/// ideographs, Hangul and most emoji, which takes two columns
+fn is_wide(c: char) -> bool {
+    matches!(
+        c,
+        '\u{1100}'..='\u{115f}'
+            | '\u{2e80}'..='\u{303e}'
+            | '\u{3041}'..='\u{33ff}'
+            | '\u{3400}'..='\u{4dbf}'
+            | '\u{4e00}'..='\u{9fff}'
+            | '\u{a000}'..='\u{a4cf}'
+            | '\u{ac00}'..='\u{d7a3}'
+            | '\u{f900}'..='\u{faff}'
+            | '\u{fe30}'..='\u{fe4f}'
+            | '\u{ff00}'..='\u{ff60}'
+            | '\u{ffe0}'..='\u{ffe6}'
+            | '\u{1f300}'..='\u{1f64f}'
+            | '\u{1f900}'..='\u{1f9ff}'
+            | '\u{20000}'..='\u{2fffd}'
+            | '\u{30000}'..='\u{3fffd}'
+    )
+}
+
+/// Returns the depth `c` is colored with and the depth after it if it is a
+/// bracket or a brace outside of a string or a comment, given the `depth`
+/// before it
+fn bracket_depths(c: char, hl_type: highlighting::Type, depth: usize) -> Option<(usize, usize)> {
+    if matches!(
+        hl_type,
+        highlighting::Type::String
+            | highlighting::Type::Character
+            | highlighting::Type::Comment
+            | highlighting::Type::MultilineComment
+            | highlighting::Type::Annotation
+    ) {
+        return None;
+    }
+    match c {
+        '{' | '[' | '(' => Some((depth, depth.saturating_add(1))),
+        '}' | ']' | ')' => {
+            let depth = depth.saturating_sub(1);
+            Some((depth, depth))
+        }
+        _ => None,
+    }
+}
+
+/// Checks if `c` is a zero-width space, joiner or mark, which takes no column
+fn is_zero_width(c: char) -> bool {
+    matches!(c, '\u{200b}'..='\u{200f}' | '\u{2060}'..='\u{2064}' | '\u{feff}')
+}
+
 fn is_separator(c: char) -> bool {
     c.is_ascii_punctuation() || c.is_ascii_whitespace()
 }
@@ -453,8 +1079,10 @@ fn is_separator(c: char) -> bool {
 #[cfg(test)]
 mod test_super {
     use super::*;
+    use crate::{FileType, HighlightingOptions};
 
     #[test]
+    #[allow(clippy::redundant_test_prefix)]
     fn test_highlight_find() {
         let mut row = Row::from("1testtest");
         row.highlighting = vec![
@@ -486,11 +1114,284 @@ mod test_super {
     }
 
     #[test]
+    fn split_at_row_boundaries() {
+        let mut at_end = Row::from("abc");
+        let empty = at_end.split(3);
+        assert_eq!((at_end.as_str(), at_end.len()), ("abc", 3));
+        assert_eq!((empty.as_str(), empty.len()), ("", 0));
+
+        let mut at_start = Row::from("abc");
+        let full = at_start.split(0);
+        assert_eq!((at_start.as_str(), at_start.len()), ("", 0));
+        assert_eq!((full.as_str(), full.len()), ("abc", 3));
+
+        let mut multibyte = Row::from("cafe\u{301} n\u{e9}");
+        let rest = multibyte.split(4);
+        assert_eq!((multibyte.as_str(), multibyte.len()), ("cafe\u{301}", 4));
+        assert_eq!((rest.as_str(), rest.len()), (" n\u{e9}", 3));
+    }
+
+    #[test]
+    fn match_ranges() {
+        let row = Row::from("ababab");
+        assert_eq!(row.match_ranges("ab", true), vec![(0, 2), (2, 4), (4, 6)]);
+        assert_eq!(row.match_ranges("aba", true), vec![(0, 3)]);
+        assert_eq!(Row::from("Ab aB").match_ranges("ab", false), vec![(0, 2), (3, 5)]);
+        assert_eq!(Row::from("Ab aB").match_ranges("ab", true), vec![]);
+    }
+
+    #[test]
+    fn highlight_trailing_whitespace() {
+        let file_type = FileType::from("main.rs");
+        let mut row = Row::from("foo   ");
+        row.highlight(file_type.highlighting_options(), &None, highlighting::HlState::Normal);
+        assert_eq!(
+            row.highlighting().get(3..),
+            Some(&[highlighting::Type::TrailingWhitespace; 3][..])
+        );
+        assert_eq!(row.highlighting().get(2), Some(&highlighting::Type::None));
+    }
+
+    #[test]
+    fn numbers_only_highlighted_in_code() {
+        for (file_name, expected) in [
+            ("notes.txt", highlighting::Type::None),
+            ("notes.md", highlighting::Type::None),
+            ("main.rs", highlighting::Type::Number),
+            ("data.json", highlighting::Type::Number),
+        ] {
+            let file_type = FileType::from(file_name);
+            let mut row = Row::from("I have 3 apples");
+            row.highlight(file_type.highlighting_options(), &None, highlighting::HlState::Normal);
+            assert_eq!(row.highlighting().get(7), Some(&expected));
+        }
+    }
+
+    #[test]
+    fn highlight_annotations_in_comments() {
+        let file_type = FileType::from("main.rs");
+        let opts = file_type.highlighting_options();
+        let mut row = Row::from("let todo = \"TODO\"; // TODO: fix this, not TODOS");
+        row.highlight(opts, &None, highlighting::HlState::Normal);
+        let types = row.highlighting();
+        assert_eq!(types.get(12), Some(&highlighting::Type::String));
+        assert_eq!(types.get(21), Some(&highlighting::Type::Comment));
+        for index in 22..26 {
+            assert_eq!(types.get(index), Some(&highlighting::Type::Annotation));
+        }
+        assert_eq!(types.get(26), Some(&highlighting::Type::Comment));
+        assert_eq!(types.get(42), Some(&highlighting::Type::Comment));
+
+        let mut block = Row::from("/* FIXME */");
+        block.highlight(opts, &None, highlighting::HlState::Normal);
+        assert_eq!(block.highlighting().get(3), Some(&highlighting::Type::Annotation));
+        assert!(block.is_comment());
+    }
+
+    #[test]
+    fn insert_in_word_matches_full_highlight() {
+        let file_type = FileType::from("main.rs");
+        let opts = file_type.highlighting_options();
+        let code = "let abc = \"xyz\"; // note 123";
+        let cases = [
+            (code, 5, 'q', true),
+            (code, 12, 'q', true),
+            (code, 22, 'q', true),
+            (code, 26, '9', true),
+            (code, 3, 'q', false),
+            (code, 11, 'q', false),
+            ("let x = 12;", 9, '5', true),
+            ("let x = 12;", 9, 'a', false),
+            ("ele = 1;", 2, 's', false),
+            ("ab_fn = 1;", 1, 'x', false),
+            ("// TOO", 5, 'D', false),
+            ("\u{1100}x = 1;", 1, '\u{1161}', false),
+        ];
+        for (text, at, c, fast) in cases {
+            let mut row = Row::from(text);
+            row.highlight(opts, &None, highlighting::HlState::Normal);
+            assert_eq!(row.insert_in_word(at, c, opts), fast, "{text} at {at}");
+            if !fast {
+                assert_eq!(row.as_str(), text);
+                continue;
+            }
+            let mut expected = Row::from(row.as_str());
+            expected.highlight(opts, &None, highlighting::HlState::Normal);
+            assert_eq!(row.highlighting(), expected.highlighting(), "{text} at {at}");
+        }
+    }
+
+    #[test]
+    fn highlight_with_built_options() {
+        let opts = HighlightingOptions::builder()
+            .keywords(&["let", "print"])
+            .line_comment("--")
+            .strings(true)
+            .numbers(true)
+            .build();
+        let file_type = FileType::new("Toy", opts);
+        assert_eq!(file_type.name(), "Toy");
+        assert_eq!(file_type.line_comment(), Some("--"));
+        let mut row = Row::from("let x = \"a\" 42 -- note");
+        row.highlight(file_type.highlighting_options(), &None, highlighting::HlState::Normal);
+        let types = row.highlighting();
+        assert_eq!(types.first(), Some(&highlighting::Type::PrimaryKeywords));
+        assert_eq!(types.get(4), Some(&highlighting::Type::None));
+        assert_eq!(types.get(9), Some(&highlighting::Type::String));
+        assert_eq!(types.get(12), Some(&highlighting::Type::Number));
+        assert_eq!(types.get(15), Some(&highlighting::Type::Comment));
+        assert_eq!(types.get(20), Some(&highlighting::Type::Comment));
+    }
+
+    #[test]
+    fn highlight_carries_block_comment_state() {
+        let file_type = FileType::from("main.rs");
+        let opts = file_type.highlighting_options();
+        let mut opening = Row::from("let x = 1; /* open");
+        let state = opening.highlight(opts, &None, highlighting::HlState::Normal);
+        assert_eq!(state, highlighting::HlState::InBlockComment);
+
+        let mut closing = Row::from("close */ let");
+        assert_eq!(closing.highlight(opts, &None, state), highlighting::HlState::Normal);
+        assert_eq!(closing.highlighting().get(7), Some(&highlighting::Type::MultilineComment));
+        assert_eq!(closing.highlighting().get(9), Some(&highlighting::Type::PrimaryKeywords));
+
+        let mut plain = Row::from("close */ let");
+        plain.highlight(opts, &None, highlighting::HlState::Normal);
+        assert_eq!(plain.highlighting().first(), Some(&highlighting::Type::None));
+    }
+
+    #[test]
+    fn x_at_column_inverts_visual_column() {
+        let row = Row::from("a\tbc");
+        let controls = ControlCharDisplay::default();
+        for x in 0..=row.len() {
+            assert_eq!(row.x_at_column(row.visual_column(x, 4, controls), 4, controls), x);
+        }
+        assert_eq!(row.x_at_column(2, 4, controls), 1);
+        assert_eq!(row.x_at_column(100, 4, controls), 4);
+    }
+
+    #[test]
+    fn highlight_brackets_counts_matched_brackets() {
+        let mut row = Row::from("((x)");
+        row.highlighting = vec![
+            highlighting::Type::Match,
+            highlighting::Type::None,
+            highlighting::Type::None,
+            highlighting::Type::None,
+        ];
+        assert_eq!(row.highlight_brackets(0), 1);
+        assert_eq!(
+            row.highlighting,
+            vec![
+                highlighting::Type::Match,
+                highlighting::Type::Bracket(1),
+                highlighting::Type::None,
+                highlighting::Type::Bracket(1),
+            ]
+        );
+        assert_eq!(row.bracket_depth(0), (1, true));
+    }
+
+    #[test]
+    fn render_expands_tabs_between_columns() {
+        let row = Row::from("a\tbc");
+        let controls = ControlCharDisplay::default();
+        assert!(row.render(0, usize::MAX, 4, controls).contains("a   bc"));
+        let window = row.render(2, 5, 4, controls);
+        assert!(window.contains("  b"));
+        assert!(!window.contains("bc"));
+        assert!(!window.contains('a'));
+    }
+
+    #[test]
+    fn control_and_zero_width_display_width() {
+        let row = Row::from("a\u{200b}b\x07c");
+        let caret = ControlCharDisplay::Caret;
+        let hidden = ControlCharDisplay::Hidden;
+        assert_eq!(row.visual_column(1, 4, caret), 1);
+        assert_eq!(row.visual_column(2, 4, caret), 1);
+        assert_eq!(row.visual_column(5, 4, caret), 5);
+        assert_eq!(row.visual_column(5, 4, hidden), 3);
+        assert_eq!(row.x_at_column(3, 4, caret), 3);
+        assert_eq!(row.x_at_column(2, 4, hidden), 4);
+        assert_eq!(row.render(0, usize::MAX, 4, caret).matches("ab^Gc").count(), 1);
+        assert_eq!(row.render(0, usize::MAX, 4, hidden).matches("abc").count(), 1);
+    }
+
+    #[test]
+    fn highlight_run_spans_keyword() {
+        let file_type = FileType::from("main.rs");
+        let mut row = Row::from("return  x");
+        row.highlight(file_type.highlighting_options(), &None, highlighting::HlState::Normal);
+        assert_eq!(row.highlight_run(0), (highlighting::Type::PrimaryKeywords, 6));
+        assert_eq!(row.highlight_run(2), (highlighting::Type::PrimaryKeywords, 4));
+        assert_eq!(row.highlight_run(6), (highlighting::Type::None, 3));
+        assert_eq!(row.highlight_run(9), (highlighting::Type::None, 0));
+
+        let mut accented = Row::from("let e\u{301}e\u{301} = 1");
+        accented.highlight(file_type.highlighting_options(), &None, highlighting::HlState::Normal);
+        assert_eq!(accented.highlight_run(4), (highlighting::Type::None, 5));
+        assert_eq!(accented.highlight_run(9), (highlighting::Type::Number, 1));
+    }
+
+    #[test]
+    fn append_keeps_multibyte_graphemes() {
+        let mut row = Row::from("caf\u{e9}");
+        row.append(&Row::from("\u{f1}and\u{fa}"));
+        assert_eq!(row.as_str(), "caf\u{e9}\u{f1}and\u{fa}");
+        assert_eq!(row.len(), 9);
+        assert_eq!(row.slice(3, 5), "\u{e9}\u{f1}");
+
+        let mut combined = Row::from("e");
+        combined.append(&Row::from("\u{301}x"));
+        assert_eq!(combined.len(), 2);
+        assert_eq!(combined.slice(0, 1), "e\u{301}");
+    }
+
+    #[test]
+    #[allow(clippy::redundant_test_prefix)]
     fn test_find() {
         let row = Row::from("1testtest");
-        assert_eq!(row.find("t", 0, SearchDirection::Forward), Some(1));
-        assert_eq!(row.find("t", 2, SearchDirection::Forward), Some(4));
-        assert_eq!(row.find("t", 5, SearchDirection::Forward), Some(5));
+        assert_eq!(row.find("t", 0, SearchDirection::Forward, None), Some(1));
+        assert_eq!(row.find("t", 2, SearchDirection::Forward, None), Some(4));
+        assert_eq!(row.find("t", 5, SearchDirection::Forward, None), Some(5));
+    }
+
+    #[test]
+    fn word_chars_decide_where_words_end() {
+        let row = Row::from("foo-bar_baz qux");
+        assert_eq!(row.word_end(0, WordCharSet::AsciiIdent), 3);
+        assert_eq!(row.word_end(3, WordCharSet::AsciiIdent), 11);
+        assert_eq!(row.word_end(0, WordCharSet::IncludingDash), 11);
+        assert_eq!(row.word_bounds(5, WordCharSet::AsciiIdent), Some((4, 11)));
+        assert_eq!(row.word_bounds(5, WordCharSet::IncludingDash), Some((0, 11)));
+        assert_eq!(row.word_bounds(3, WordCharSet::AsciiIdent), None);
+        assert_eq!(row.find("bar", 0, SearchDirection::Forward, Some(WordCharSet::AsciiIdent)), None);
+        assert_eq!(row.find("foo", 0, SearchDirection::Forward, Some(WordCharSet::AsciiIdent)), Some(0));
+        assert_eq!(row.find("foo", 0, SearchDirection::Forward, Some(WordCharSet::IncludingDash)), None);
+    }
+
+    #[test]
+    fn find_backward_lands_strictly_before() {
+        let row = Row::from("ab ab ab");
+        assert_eq!(row.find("ab", 6, SearchDirection::Backward, None), Some(3));
+        assert_eq!(row.find("ab", 3, SearchDirection::Backward, None), Some(0));
+        assert_eq!(row.find("ab", 0, SearchDirection::Backward, None), None);
+        assert_eq!(row.find("ab", 7, SearchDirection::Backward, None), Some(6));
+        assert_eq!(row.find("ab", 4, SearchDirection::Backward, None), Some(3));
+        assert_eq!(row.find("ab", 100, SearchDirection::Backward, None), Some(6));
+        assert_eq!(row.find("aa", 2, SearchDirection::Backward, None), None);
+        assert_eq!(Row::from("aaa").find("aa", 2, SearchDirection::Backward, None), Some(1));
+    }
+
+    #[test]
+    fn find_whole_word() {
+        let row = Row::from("in inside begin in");
+        assert_eq!(row.find("in", 1, SearchDirection::Forward, Some(WordCharSet::default())), Some(16));
+        assert_eq!(row.find("in", 16, SearchDirection::Backward, Some(WordCharSet::default())), Some(0));
+        assert_eq!(row.find("in", 16, SearchDirection::Backward, None), Some(13));
     }
 
 }
\ No newline at end of file
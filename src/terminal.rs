@@ -1,5 +1,5 @@
 use crate::Position;
-use std::io::{self, stdout, Error, ErrorKind, Write};
+use std::io::{self, stdout, Error, Write};
 use termion::color;
 use termion::event::Key;
 use termion::input::TermRead;
@@ -21,6 +21,7 @@ impl Terminal {
     /// # Errors
     /// It will return `Err` if `termion::terminal_size()`
     /// fails to get the terminal's size
+    #[allow(clippy::should_implement_trait)]
     pub fn default() -> Result<Self, std::io::Error> {
         if let Ok(size) = termion::terminal_size() {
             if let Ok(raw_terminal) = stdout().into_raw_mode() {
@@ -32,14 +33,12 @@ impl Terminal {
                     _stdout: raw_terminal,
                 })
             } else {
-                Err(Error::new(
-                    ErrorKind::Other,
+                Err(Error::other(
                     "Something unexpected happening while trying to enter raw mode",
                 ))
             }
         } else {
-            Err(Error::new(
-                ErrorKind::Other,
+            Err(Error::other(
                 "Something unexpected happening while trying to get the terminal size.",
             ))
         }
@@ -56,6 +55,10 @@ impl Terminal {
     }
 
     /// Set the cursor position on the terminal
+    ///
+    /// # Panics
+    ///
+    /// It will panic if the position does not fit into a `u16`.
     pub fn cursor_position(position: &Position) {
         let Position { mut x, mut y } = *position;
         x = x.saturating_add(1);